@@ -5,8 +5,23 @@ import numpy as np
 
 import chess.pgn
 
+try:
+    # The Rust encoders from pgn-to-numpy-rust/python, if they are installed.
+    import neural_chess
+except ImportError:
+    neural_chess = None
+
 
 def board_to_input(board) -> np.ndarray:
+    if neural_chess is not None:
+        return neural_chess.fen_to_input(board.fen()).astype(float)
+    return python_board_to_input(board)
+
+
+def python_board_to_input(board) -> np.ndarray:
+    """
+    The pure Python encoding of board_to_input, which the Rust encoder has to match.
+    """
     result = [float(board.turn)]
     for square in chess.SQUARES:
         piece = board.piece_at(square)
@@ -27,6 +42,41 @@ def board_to_input(board) -> np.ndarray:
     return np.array(result)
 
 
+def unpack_input(packed: np.ndarray) -> np.ndarray:
+    """
+    Unpacks inputs stored with --packed ("packing": "packbits" in the manifest).
+    Works on a single packed row as well as on a whole (N, 105) array.
+    """
+    return np.unpackbits(packed, axis=-1, count=(6 * 2 + 1) * 64 + 1).astype(bool)
+
+
+def unpack_legal_moves(mask: np.ndarray) -> np.ndarray:
+    """
+    Unpacks legal move masks written with --legal-moves into one bool per move index
+    (from * 64 + to), e.g. for a masked softmax.
+    """
+    return np.unpackbits(mask, axis=-1).astype(bool)
+
+
+def parse_tfrecord(record, label: str = "move"):
+    """
+    Parses a tf.train.Example written with --format tfrecord into (input, label).
+    The label is "move" (int64) or "eval" (float32), depending on the dataset.
+    """
+    import tensorflow as tf
+
+    example = tf.io.parse_single_example(
+        record,
+        {
+            "input": tf.io.FixedLenFeature([], tf.string),
+            label: tf.io.FixedLenFeature([], tf.int64 if label == "move" else tf.float32),
+        },
+    )
+    packed = tf.io.decode_raw(example["input"], tf.uint8)
+    bits = tf.bitwise.right_shift(packed[:, None], tf.range(7, -1, -1, dtype=tf.uint8)) & 1
+    return tf.reshape(bits, [-1])[: (6 * 2 + 1) * 64 + 1], example[label]
+
+
 def move_to_output(move) -> np.ndarray:
     """
     Converts a chess.Move to a list of floats.
@@ -42,6 +92,14 @@ def move_to_complete_output(move) -> int:
     return move.from_square * 64 + move.to_square
 
 
+def mirror_complete_output(index: int) -> int:
+    """
+    Mirrors a move index of a --perspective side-to-move dataset back to the real board
+    (and vice versa) for positions with Black to move.
+    """
+    return index ^ (56 * 64 + 56)
+
+
 def output_to_move(noutput: list[float]) -> chess.Move:
     from_square = max(enumerate(noutput[:64]), key=lambda p: p[1])[0]
     to_square = max(enumerate(noutput[64:]), key=lambda p: p[1])[0]
@@ -0,0 +1,126 @@
+//! A minimal UCI engine for testing `annotate` without a real engine. It scores each legal move
+//! by the material balance after it, without searching, and reports the best moves as its
+//! principal variations.
+//!
+//! ```sh
+//! cargo build --example stub_engine
+//! pgn-to-numpy -o annotated annotate --engine target/debug/examples/stub_engine -f games.pgn
+//! ```
+
+use std::io::{self, BufRead, Write};
+
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Color, Move, Position, Role};
+
+/// The score of a move that mates, reported as `mate 1`.
+const MATE: i32 = 100_000;
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut chess = Chess::default();
+    let mut multi_pv = 1;
+
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["uci"] => {
+                writeln!(stdout, "id name stub_engine")?;
+                writeln!(
+                    stdout,
+                    "option name MultiPV type spin default 1 min 1 max 500"
+                )?;
+                writeln!(stdout, "uciok")?;
+            }
+            ["isready"] => writeln!(stdout, "readyok")?,
+            ["setoption", "name", "MultiPV", "value", value] => {
+                multi_pv = value.parse().unwrap_or(1);
+            }
+            ["position", rest @ ..] => {
+                if let Some(position) = parse_position(rest) {
+                    chess = position;
+                }
+            }
+            ["go", ..] => {
+                let mut moves: Vec<(i32, Move)> = chess
+                    .legal_moves()
+                    .into_iter()
+                    .map(|m| (score(&chess, &m), m))
+                    .collect();
+                moves.sort_by_key(|(score, _)| -score);
+                for (index, &(score, ref m)) in moves.iter().take(multi_pv).enumerate() {
+                    let score = match score {
+                        MATE => "mate 1".to_owned(),
+                        _ => format!("cp {score}"),
+                    };
+                    writeln!(
+                        stdout,
+                        "info depth 1 multipv {} score {score} pv {}",
+                        index + 1,
+                        m.to_uci(CastlingMode::Standard),
+                    )?;
+                }
+                match moves.first() {
+                    Some((_, m)) => {
+                        writeln!(stdout, "bestmove {}", m.to_uci(CastlingMode::Standard))?
+                    }
+                    None => writeln!(stdout, "bestmove (none)")?,
+                }
+            }
+            ["quit"] => break,
+            _ => {}
+        }
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Parses the arguments of `position`: `startpos` or `fen <fen>`, then optionally `moves ...`.
+fn parse_position(tokens: &[&str]) -> Option<Chess> {
+    let (mut chess, rest) = match tokens {
+        ["startpos", rest @ ..] => (Chess::default(), rest),
+        ["fen", rest @ ..] => {
+            let end = rest
+                .iter()
+                .position(|&token| token == "moves")
+                .unwrap_or(rest.len());
+            let fen: Fen = rest[..end].join(" ").parse().ok()?;
+            (
+                fen.into_position(CastlingMode::Standard).ok()?,
+                &rest[end..],
+            )
+        }
+        _ => return None,
+    };
+    if let ["moves", moves @ ..] = rest {
+        for uci in moves {
+            let m = uci.parse::<Uci>().ok()?.to_move(&chess).ok()?;
+            chess.play_unchecked(&m);
+        }
+    }
+    Some(chess)
+}
+
+/// The material balance in centipawns after `m`, from the point of view of the side to move.
+fn score(chess: &Chess, m: &Move) -> i32 {
+    let mut after = chess.clone();
+    after.play_unchecked(m);
+    if after.is_checkmate() {
+        return MATE;
+    }
+    let board = after.board();
+    let material = |color: Color| -> i32 {
+        [
+            (Role::Pawn, 100),
+            (Role::Knight, 300),
+            (Role::Bishop, 300),
+            (Role::Rook, 500),
+            (Role::Queen, 900),
+        ]
+        .into_iter()
+        .map(|(role, value)| value * (board.by_color(color) & board.by_role(role)).count() as i32)
+        .sum()
+    };
+    material(chess.turn()) - material(!chess.turn())
+}
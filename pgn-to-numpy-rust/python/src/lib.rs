@@ -0,0 +1,138 @@
+//! Python bindings for the encoders of `neural_chess`, built with maturin:
+//!
+//! ```text
+//! cd pgn-to-numpy-rust/python && maturin develop --release
+//! ```
+//!
+//! The extension module can not be tested with `cargo test`. `tests/test_parity.py` checks it
+//! against the pure Python encoders of `board_code.py` instead (`pip install .[test]`, then
+//! `pytest tests`).
+
+use std::fs::File;
+
+use neural_chess::{
+    chess_to_input, chess_to_planes, eval_to_output, move_to_output, output_to_eval,
+    output_to_move, output_to_uci, visitor::MoveVisitor, INPUT_LENGTH, PLANES,
+};
+use numpy::{ndarray::Array2, IntoPyArray, PyArray1, PyArray2, PyArray3};
+use pgn_reader::BufferedReader;
+use pyo3::{exceptions::PyValueError, prelude::*};
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Position};
+
+fn parse_fen(fen: &str) -> PyResult<Chess> {
+    let parsed = fen
+        .parse::<Fen>()
+        .map_err(|err| PyValueError::new_err(format!("invalid FEN {fen:?}: {err}")))?;
+    let mode = CastlingMode::detect(parsed.as_setup());
+    parsed
+        .into_position(mode)
+        .map_err(|err| PyValueError::new_err(format!("illegal position {fen:?}: {err}")))
+}
+
+/// Encodes a position like `chess_to_input`, as a bool array of length 833.
+#[pyfunction]
+fn fen_to_input<'py>(py: Python<'py>, fen: &str) -> PyResult<Bound<'py, PyArray1<bool>>> {
+    Ok(chess_to_input(&parse_fen(fen)?).to_vec().into_pyarray(py))
+}
+
+/// Encodes a position as an (18, 8, 8) bool array, like `--layout planes-first`.
+#[pyfunction]
+fn fen_to_planes<'py>(py: Python<'py>, fen: &str) -> PyResult<Bound<'py, PyArray3<bool>>> {
+    let planes = chess_to_planes(&parse_fen(fen)?);
+    let values = planes.iter().flatten().copied().collect();
+    Ok(
+        numpy::ndarray::Array3::from_shape_vec((PLANES.len(), 8, 8), values)
+            .expect("planes have 64 squares")
+            .into_pyarray(py),
+    )
+}
+
+/// The label (`from * 64 + to`) of a legal move, given in UCI notation.
+#[pyfunction]
+fn move_to_label(fen: &str, uci: &str) -> PyResult<u16> {
+    let chess = parse_fen(fen)?;
+    let m = uci
+        .parse::<Uci>()
+        .map_err(|err| PyValueError::new_err(format!("invalid UCI move {uci:?}: {err}")))?
+        .to_move(&chess)
+        .map_err(|err| PyValueError::new_err(format!("illegal move {uci:?}: {err}")))?;
+    Ok(move_to_output(&m))
+}
+
+/// The legal move with the given label in UCI notation, preferring queen promotions, or `None`.
+#[pyfunction]
+fn label_to_move(fen: &str, label: u16) -> PyResult<Option<String>> {
+    let chess = parse_fen(fen)?;
+    Ok(output_to_move(&chess, label)
+        .map(|m| Uci::from_move(&m, chess.castles().mode()).to_string()))
+}
+
+/// The squares of a label in UCI notation, without checking that the move is legal.
+#[pyfunction]
+fn label_to_uci(label: u16) -> PyResult<String> {
+    output_to_uci(label)
+        .map(|uci| uci.to_string())
+        .ok_or_else(|| PyValueError::new_err(format!("label {label} out of range")))
+}
+
+/// Maps an evaluation in pawns to the sigmoid output of `pgn-to-eval` datasets.
+#[pyfunction(name = "eval_to_output")]
+fn py_eval_to_output(eval: f32) -> f32 {
+    eval_to_output(eval)
+}
+
+/// The inverse of `eval_to_output`.
+#[pyfunction(name = "output_to_eval")]
+fn py_output_to_eval(output: f32) -> f32 {
+    output_to_eval(output)
+}
+
+/// Converts the games of a PGN file like `pgn-to-npy`, but in memory and without deduplication.
+///
+/// Returns an (N, 833) bool array of inputs and an (N,) uint16 array of move labels, with at
+/// most `limit` positions.
+#[pyfunction]
+#[pyo3(signature = (path, limit = None))]
+#[allow(clippy::type_complexity)]
+fn pgn_to_numpy<'py>(
+    py: Python<'py>,
+    path: &str,
+    limit: Option<usize>,
+) -> PyResult<(Bound<'py, PyArray2<bool>>, Bound<'py, PyArray1<u16>>)> {
+    let pgn = File::open(path)?;
+    let (inputs, labels) = py.detach(|| {
+        let mut reader = BufferedReader::new(pgn);
+        let mut visitor = MoveVisitor::new(None);
+        let mut inputs = Vec::new();
+        let mut labels = Vec::new();
+        let samples = std::iter::from_fn(|| reader.read_game(&mut visitor).ok().flatten())
+            .flatten()
+            .flatten()
+            .take(limit.unwrap_or(usize::MAX));
+        for sample in samples {
+            inputs.extend(chess_to_input(&sample.chess));
+            labels.push(move_to_output(&sample.label));
+        }
+        (inputs, labels)
+    });
+
+    let inputs =
+        Array2::from_shape_vec((labels.len(), INPUT_LENGTH), inputs).expect("one input per label");
+    Ok((inputs.into_pyarray(py), labels.into_pyarray(py)))
+}
+
+#[pymodule]
+#[pyo3(name = "neural_chess")]
+fn neural_chess_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("INPUT_LENGTH", INPUT_LENGTH)?;
+    m.add("PLANES", PLANES.to_vec())?;
+    m.add_function(wrap_pyfunction!(fen_to_input, m)?)?;
+    m.add_function(wrap_pyfunction!(fen_to_planes, m)?)?;
+    m.add_function(wrap_pyfunction!(move_to_label, m)?)?;
+    m.add_function(wrap_pyfunction!(label_to_move, m)?)?;
+    m.add_function(wrap_pyfunction!(label_to_uci, m)?)?;
+    m.add_function(wrap_pyfunction!(py_eval_to_output, m)?)?;
+    m.add_function(wrap_pyfunction!(py_output_to_eval, m)?)?;
+    m.add_function(wrap_pyfunction!(pgn_to_numpy, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,61 @@
+"""
+Checks that the Rust encoders of the neural_chess module match the pure Python ones of
+board_code.py, which board_to_input replaces with them when the module is installed.
+
+    cd pgn-to-numpy-rust/python && maturin develop && pytest tests
+"""
+
+import sys
+from pathlib import Path
+
+import chess
+import numpy as np
+import pytest
+
+neural_chess = pytest.importorskip("neural_chess")
+
+sys.path.insert(0, str(Path(__file__).resolve().parents[3]))
+from board_code import move_to_complete_output, python_board_to_input  # noqa: E402
+
+FENS = [
+    chess.STARTING_FEN,
+    # Castling on both sides, with Black to move.
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+    # En passant.
+    "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+    # Promotions, also by capturing.
+    "1r2k3/2P5/8/8/8/8/8/4K3 w - - 0 1",
+]
+
+
+def label_to_move(board: chess.Board, label: int):
+    """
+    The legal move with the label, preferring queen promotions, like neural_chess.label_to_move.
+    """
+    moves = [move for move in board.legal_moves if move_to_complete_output(move) == label]
+    if not moves:
+        return None
+    return max(moves, key=lambda move: move.promotion == chess.QUEEN).uci()
+
+
+@pytest.mark.parametrize("fen", FENS)
+def test_input(fen):
+    board = chess.Board(fen)
+    rust = neural_chess.fen_to_input(fen)
+    assert rust.shape == (neural_chess.INPUT_LENGTH,)
+    assert np.array_equal(rust.astype(float), python_board_to_input(board))
+
+
+@pytest.mark.parametrize("fen", FENS)
+def test_move_to_label(fen):
+    board = chess.Board(fen)
+    for move in board.legal_moves:
+        assert neural_chess.move_to_label(fen, move.uci()) == move_to_complete_output(move)
+
+
+@pytest.mark.parametrize("fen", FENS)
+def test_label_to_move(fen):
+    board = chess.Board(fen)
+    # Every square pair, so that labels without a legal move are covered as well.
+    for label in range(64 * 64):
+        assert neural_chess.label_to_move(fen, label) == label_to_move(board, label)
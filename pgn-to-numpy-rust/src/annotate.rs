@@ -0,0 +1,156 @@
+use std::{error::Error, path::Path, thread};
+
+use clap::ArgMatches;
+use fs_err::File;
+use neural_chess::{
+    dataset::{chunk_count, ChunkReader},
+    engine::{Analysis, EnginePool, Limit},
+    eval_to_output, input_to_chess,
+    manifest::Manifest,
+    visitor::MoveVisitor,
+    Sample,
+};
+use pgn_reader::BufferedReader;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use shakmaty::{variant::VariantPosition, Move, Position};
+
+use crate::{dataset_writer, ARGS};
+
+pub fn main(options: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let writer = dataset_writer()?;
+    let manifest = writer.manifest().clone();
+    let policy = manifest.policy_temperature.zip(manifest.policy_top_k);
+
+    let limit = match options.get_one::<u64>("nodes") {
+        Some(&nodes) => Limit::Nodes(nodes),
+        None => Limit::Depth(*options.get_one::<u32>("depth").expect("default value")),
+    };
+    let engines = match options.get_one::<usize>("engines") {
+        Some(&engines) => engines,
+        None => thread::available_parallelism()?.get(),
+    };
+    let pool = EnginePool::spawn(
+        Path::new(options.get_one::<String>("engine").expect("required")),
+        engines,
+        policy.map_or(1, |(_, top_k)| top_k),
+        manifest.variant,
+    )?;
+
+    let positions: Box<dyn Iterator<Item = Sample<()>> + Send> =
+        match options.get_one::<String>("pgn-file") {
+            Some(pgn) => Box::new(pgn_positions(pgn, &manifest)?),
+            None => Box::new(dataset_positions(
+                Path::new(options.get_one::<String>("dir").expect("default value")),
+                options.get_one::<String>("dataset").expect("required"),
+            )?),
+        };
+    let sample_rate = *options
+        .get_one::<f64>("sample_rate")
+        .expect("default value");
+    if !(0.0..=1.0).contains(&sample_rate) {
+        Err("the sample rate must be between 0 and 1")?;
+    }
+    let mut rng = StdRng::from_entropy();
+    let positions = positions.filter(move |_| rng.gen_bool(sample_rate));
+
+    let mut error = None;
+    let analysed = pool
+        .analyse(positions, limit)
+        .map_while(|result| result.map_err(|err| error = Some(err)).ok());
+    // Positions the engine gives no usable answer for, e.g. mates or an illegal best move, are
+    // skipped.
+    if options.get_flag("best_move") {
+        writer.write_moves(analysed.filter_map(|(sample, analysis)| {
+            let m = analysis.best_move.as_ref()?.to_move(&sample.chess).ok()?;
+            label_sample(sample, &analysis, m, policy, &manifest)
+        }))?;
+    } else {
+        writer.write(analysed.filter_map(|(sample, analysis)| {
+            let eval = analysis.eval(sample.chess.turn())?;
+            label_sample(sample, &analysis, eval_to_output(eval), policy, &manifest)
+        }))?;
+    }
+    if let Some(err) = error {
+        Err(format!("engine failed: {err}"))?;
+    }
+
+    Ok(())
+}
+
+/// Gives a sample its label and, if `policy` gives a temperature, the soft policy target of the
+/// engine lines.
+fn label_sample<T>(
+    sample: Sample<()>,
+    analysis: &Analysis,
+    label: T,
+    policy: Option<(f32, usize)>,
+    manifest: &Manifest,
+) -> Option<Sample<T>> {
+    let policy = match policy {
+        Some((temperature, _)) => {
+            Some(analysis.policy(&sample.chess, manifest.castling, temperature)?)
+        }
+        None => None,
+    };
+    Some(Sample {
+        chess: sample.chess,
+        label,
+        game: sample.game,
+        policy,
+        history: sample.history,
+    })
+}
+
+/// The positions of the games that `pgn-to-npy` would convert.
+fn pgn_positions(
+    pgn: &str,
+    manifest: &Manifest,
+) -> Result<impl Iterator<Item = Sample<()>> + Send, Box<dyn Error>> {
+    let mut reader = BufferedReader::new(File::open(pgn)?);
+    let mut visitor = MoveVisitor::new(ARGS.get_one::<usize>("history").copied())
+        .with_variant(manifest.variant)
+        .with_castling(manifest.castling);
+    Ok(
+        std::iter::from_fn(move || reader.read_game(&mut visitor).ok().flatten())
+            .flatten()
+            .flatten()
+            .map(|sample: Sample<Move>| Sample {
+                chess: sample.chess,
+                label: (),
+                game: sample.game,
+                policy: None,
+                history: sample.history,
+            }),
+    )
+}
+
+/// The positions of an existing dataset. Castling rights are guessed like in
+/// [`input_to_chess`], and rows that are not legal positions are skipped.
+///
+/// Each chunk is only opened when it is reached. A chunk that can not be read is reported and
+/// the remaining chunks are still annotated.
+fn dataset_positions(
+    dir: &Path,
+    prefix: &str,
+) -> Result<impl Iterator<Item = Sample<()>> + Send, Box<dyn Error>> {
+    let chunks = chunk_count(dir, prefix)?;
+    if chunks == 0 {
+        Err(format!("dataset {prefix} has no files"))?;
+    }
+    let (dir, prefix) = (dir.to_owned(), prefix.to_owned());
+    Ok((0..chunks)
+        .filter_map(move |chunk_index| {
+            ChunkReader::open(&dir, &prefix, chunk_index)
+                .map_err(|err| eprintln!("Skipping chunk {chunk_index}: {err}"))
+                .ok()
+                .map(|reader| (chunk_index, reader))
+        })
+        .flat_map(|(chunk_index, reader)| {
+            reader.map_while(move |row| {
+                row.map_err(|err| eprintln!("Skipping the rest of chunk {chunk_index}: {err}"))
+                    .ok()
+            })
+        })
+        .filter_map(|(input, _)| input_to_chess(&input).ok())
+        .map(|chess| Sample::new(VariantPosition::from(chess), ())))
+}
@@ -0,0 +1,315 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{
+    builder::{FixedSizeBinaryBuilder, Float32Builder, StringBuilder, UInt16Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use fs_err::{self as fs, File};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use shakmaty::{fen::Fen, EnPassantMode};
+
+use super::{already_exists, OutputBackend, OutputLabel};
+use crate::{
+    dataset::Label,
+    manifest::{Format, Manifest},
+    pack_input, Sample, INPUT_LENGTH, PACKED_INPUT_LENGTH,
+};
+
+/// Writes one Parquet or Arrow IPC file per chunk to `<prefix>_parquet` or `<prefix>_arrow`.
+///
+/// Every chunk is a single record batch (and row group), with one row per position.
+pub struct ArrowBackend {
+    dir: PathBuf,
+    format: Format,
+    schema: SchemaRef,
+    chunk: Option<(usize, ChunkBuilder)>,
+    boards_per_file: usize,
+}
+
+/// Column builders for the chunk that is currently being written.
+struct ChunkBuilder {
+    fen: StringBuilder,
+    input: FixedSizeBinaryBuilder,
+    move_index: UInt16Builder,
+    eval: Float32Builder,
+    result: StringBuilder,
+    white_elo: UInt16Builder,
+    black_elo: UInt16Builder,
+    time_control: StringBuilder,
+    event: StringBuilder,
+}
+
+impl ChunkBuilder {
+    fn new(capacity: usize) -> Self {
+        Self {
+            fen: StringBuilder::with_capacity(capacity, capacity * 64),
+            input: FixedSizeBinaryBuilder::with_capacity(capacity, PACKED_INPUT_LENGTH as i32),
+            move_index: UInt16Builder::with_capacity(capacity),
+            eval: Float32Builder::with_capacity(capacity),
+            result: StringBuilder::new(),
+            white_elo: UInt16Builder::with_capacity(capacity),
+            black_elo: UInt16Builder::with_capacity(capacity),
+            time_control: StringBuilder::new(),
+            event: StringBuilder::new(),
+        }
+    }
+
+    fn finish(mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.fen.finish()),
+            Arc::new(self.input.finish()),
+            Arc::new(self.move_index.finish()),
+            Arc::new(self.eval.finish()),
+            Arc::new(self.result.finish()),
+            Arc::new(self.white_elo.finish()),
+            Arc::new(self.black_elo.finish()),
+            Arc::new(self.time_control.finish()),
+            Arc::new(self.event.finish()),
+        ]
+    }
+}
+
+fn schema() -> Schema {
+    Schema::new(vec![
+        Field::new("fen", DataType::Utf8, false),
+        Field::new(
+            "input",
+            DataType::FixedSizeBinary(PACKED_INPUT_LENGTH as i32),
+            false,
+        ),
+        Field::new("move", DataType::UInt16, true),
+        Field::new("eval", DataType::Float32, true),
+        Field::new("result", DataType::Utf8, true),
+        Field::new("white_elo", DataType::UInt16, true),
+        Field::new("black_elo", DataType::UInt16, true),
+        Field::new("time_control", DataType::Utf8, true),
+        Field::new("event", DataType::Utf8, true),
+    ])
+}
+
+impl ArrowBackend {
+    pub fn create(dir: &Path, prefix: &str, manifest: &Manifest) -> io::Result<Self> {
+        let extension = match manifest.format {
+            Format::Parquet => "parquet",
+            Format::Arrow => "arrow",
+            Format::Npy | Format::Npz | Format::Tfrecord => unreachable!("not an Arrow format"),
+        };
+        let dir = dir.join(format!("{prefix}_{extension}"));
+
+        if dir.try_exists()? {
+            return Err(already_exists(&dir));
+        }
+        fs::create_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            format: manifest.format,
+            schema: Arc::new(schema()),
+            chunk: None,
+            boards_per_file: manifest.boards_per_file,
+        })
+    }
+
+    fn write_batch(&self, chunk_index: usize, batch: &RecordBatch) -> io::Result<()> {
+        match self.format {
+            Format::Parquet => {
+                let file = File::create(self.dir.join(format!("{chunk_index}.parquet")))?;
+                let properties = WriterProperties::builder()
+                    .set_max_row_group_size(self.boards_per_file)
+                    .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                    .build();
+                let mut writer = ArrowWriter::try_new(file, self.schema.clone(), Some(properties))
+                    .map_err(io::Error::other)?;
+                writer.write(batch).map_err(io::Error::other)?;
+                writer.close().map_err(io::Error::other)?;
+            }
+            Format::Arrow => {
+                let file = File::create(self.dir.join(format!("{chunk_index}.arrow")))?;
+                let mut writer =
+                    FileWriter::try_new(file, &self.schema).map_err(io::Error::other)?;
+                writer.write(batch).map_err(io::Error::other)?;
+                writer.finish().map_err(io::Error::other)?;
+            }
+            Format::Npy | Format::Npz | Format::Tfrecord => unreachable!("not an Arrow format"),
+        }
+        Ok(())
+    }
+}
+
+impl<T: OutputLabel> OutputBackend<T> for ArrowBackend {
+    fn begin_chunk(&mut self, chunk_index: usize) -> io::Result<()> {
+        self.chunk = Some((chunk_index, ChunkBuilder::new(self.boards_per_file)));
+        Ok(())
+    }
+
+    fn push(&mut self, input: &[bool; INPUT_LENGTH], sample: &Sample<T>) -> io::Result<()> {
+        let (_, builder) = self.chunk.as_mut().expect("chunk not started");
+
+        builder.fen.append_value(
+            Fen::from_position(sample.chess.clone(), EnPassantMode::Legal).to_string(),
+        );
+        builder
+            .input
+            .append_value(pack_input(input))
+            .map_err(io::Error::other)?;
+
+        match sample.label.into() {
+            Label::Move(index) => {
+                builder.move_index.append_value(index);
+                builder.eval.append_null();
+            }
+            Label::Eval(eval) => {
+                builder.move_index.append_null();
+                builder.eval.append_value(eval);
+            }
+        }
+
+        let game = sample.game.as_deref();
+        builder
+            .result
+            .append_option(game.and_then(|game| game.result.as_deref()));
+        builder
+            .white_elo
+            .append_option(game.and_then(|game| game.white_elo));
+        builder
+            .black_elo
+            .append_option(game.and_then(|game| game.black_elo));
+        builder
+            .time_control
+            .append_option(game.and_then(|game| game.time_control.as_deref()));
+        builder
+            .event
+            .append_option(game.and_then(|game| game.event.as_deref()));
+
+        Ok(())
+    }
+
+    fn finish_chunk(&mut self) -> io::Result<()> {
+        let (chunk_index, builder) = self.chunk.take().expect("chunk not started");
+        let batch = RecordBatch::try_new(self.schema.clone(), builder.finish())
+            .map_err(io::Error::other)?;
+        self.write_batch(chunk_index, &batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use arrow_array::{Array, FixedSizeBinaryArray, Float32Array, StringArray, UInt16Array};
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use shakmaty::{uci::Uci, Chess, Position};
+
+    use super::*;
+    use crate::{chess_to_input, unpack_input, DatasetConfig, DatasetWriter, GameInfo, Sample};
+
+    /// Writes the starting position, with its game, and the position after 1. e4 as a single
+    /// chunk and reads the chunk back.
+    fn write_and_read(format: Format) -> RecordBatch {
+        let dir = env::temp_dir().join(format!("neural-chess-{format:?}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temporary directory");
+
+        let start = Chess::default();
+        let e4: Uci = "e2e4".parse().expect("valid UCI");
+        let after_e4 = start
+            .clone()
+            .play(&e4.to_move(&start).expect("legal"))
+            .expect("legal");
+        let game = GameInfo {
+            event: Some("Rated Blitz game".to_owned()),
+            white_elo: Some(2000),
+            black_elo: None,
+            time_control: Some("300+0".to_owned()),
+            result: Some("1-0".to_owned()),
+        };
+        let samples = [
+            Sample {
+                game: Some(Arc::new(game)),
+                ..Sample::new(start, 12 * 64 + 28_u16)
+            },
+            Sample::new(after_e4, 52 * 64 + 36_u16),
+        ];
+        DatasetWriter::builder("columns")
+            .output_dir(&dir)
+            .config(DatasetConfig {
+                total: 2,
+                boards_per_file: 2,
+                format,
+                ..DatasetConfig::default()
+            })
+            .build()
+            .expect("valid config")
+            .write(samples.into_iter())
+            .expect("dataset written");
+
+        let mut batches = match format {
+            Format::Parquet => {
+                let file = File::open(dir.join("columns_parquet/0.parquet")).expect("chunk");
+                ParquetRecordBatchReaderBuilder::try_new(file.into_parts().0)
+                    .and_then(|builder| builder.build())
+                    .expect("Parquet file")
+                    .collect::<Result<Vec<_>, _>>()
+            }
+            _ => {
+                let file = File::open(dir.join("columns_arrow/0.arrow")).expect("chunk");
+                FileReader::try_new(file, None)
+                    .expect("Arrow IPC file")
+                    .collect::<Result<Vec<_>, _>>()
+            }
+        }
+        .expect("record batches");
+        fs::remove_dir_all(&dir).expect("temporary directory");
+        assert_eq!(batches.len(), 1);
+        batches.remove(0)
+    }
+
+    fn column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> &'a T {
+        batch
+            .column_by_name(name)
+            .and_then(|column| column.as_any().downcast_ref())
+            .expect("column of the schema")
+    }
+
+    #[test]
+    fn parquet_and_arrow_chunks_round_trip() {
+        for format in [Format::Parquet, Format::Arrow] {
+            let batch = write_and_read(format);
+            assert_eq!(batch.schema().as_ref(), &schema());
+            assert_eq!(batch.num_rows(), 2);
+
+            let fen = column::<StringArray>(&batch, "fen");
+            assert_eq!(
+                fen.value(1),
+                "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+            );
+            let input = column::<FixedSizeBinaryArray>(&batch, "input");
+            assert_eq!(
+                unpack_input(input.value(0)),
+                chess_to_input(&Chess::default())
+            );
+            let moves = column::<UInt16Array>(&batch, "move");
+            assert_eq!(moves.values().as_ref(), [12 * 64 + 28, 52 * 64 + 36]);
+            assert_eq!(column::<Float32Array>(&batch, "eval").null_count(), 2);
+
+            // Only the first position has a game.
+            let event = column::<StringArray>(&batch, "event");
+            assert_eq!(event.value(0), "Rated Blitz game");
+            assert!(event.is_null(1));
+            assert_eq!(column::<UInt16Array>(&batch, "white_elo").value(0), 2000);
+            assert!(column::<UInt16Array>(&batch, "black_elo").is_null(0));
+            assert_eq!(column::<StringArray>(&batch, "result").value(0), "1-0");
+        }
+    }
+}
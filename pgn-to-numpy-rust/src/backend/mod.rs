@@ -0,0 +1,80 @@
+use std::{fmt::Debug, io, path::Path};
+
+use crate::{
+    dataset::Label,
+    flip_output,
+    manifest::{Format, Manifest},
+    mirror_output, Sample, INPUT_LENGTH,
+};
+
+mod arrow;
+mod npy;
+mod npz;
+mod tfrecord;
+
+/// Labels that every backend can write: move indices (`u16`) and evaluations (`f32`).
+pub trait OutputLabel: npyz::Serialize + npyz::AutoSerialize + Into<Label> + Copy + Debug {
+    /// The label of the position with the board flipped and the colours swapped.
+    fn mirrored(self) -> Self;
+
+    /// The label of the position mirrored horizontally (a↔h).
+    fn flipped(self) -> Self;
+}
+
+impl OutputLabel for u16 {
+    fn mirrored(self) -> Self {
+        mirror_output(self)
+    }
+
+    fn flipped(self) -> Self {
+        flip_output(self)
+    }
+}
+
+impl OutputLabel for f32 {
+    /// Evaluations are from White's point of view, so the sigmoid output is inverted.
+    fn mirrored(self) -> Self {
+        1.0 - self
+    }
+
+    fn flipped(self) -> Self {
+        self
+    }
+}
+
+/// Writes the chunks of a dataset in one storage format.
+///
+/// [`DatasetWriter::write`](crate::DatasetWriter::write) deduplicates and chunks the samples, a
+/// backend only has to store them.
+pub trait OutputBackend<T> {
+    fn begin_chunk(&mut self, chunk_index: usize) -> io::Result<()>;
+
+    fn push(&mut self, input: &[bool; INPUT_LENGTH], sample: &Sample<T>) -> io::Result<()>;
+
+    fn finish_chunk(&mut self) -> io::Result<()>;
+}
+
+/// The error for a dataset directory that would be overwritten.
+fn already_exists(dir: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} already exists", dir.display()),
+    )
+}
+
+/// Creates the backend for the format in the manifest, writing into `dir`. Fails if the dataset
+/// already exists.
+pub fn create<T: OutputLabel + 'static>(
+    dir: &Path,
+    prefix: &str,
+    manifest: &Manifest,
+) -> io::Result<Box<dyn OutputBackend<T>>> {
+    Ok(match manifest.format {
+        Format::Npy => Box::new(npy::NpyBackend::create(dir, prefix, manifest)?),
+        Format::Npz => Box::new(npz::NpzBackend::create(dir, prefix, manifest)?),
+        Format::Tfrecord => Box::new(tfrecord::TfrecordBackend::create(dir, prefix, manifest)?),
+        Format::Parquet | Format::Arrow => {
+            Box::new(arrow::ArrowBackend::create(dir, prefix, manifest)?)
+        }
+    })
+}
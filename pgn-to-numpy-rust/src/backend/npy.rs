@@ -0,0 +1,423 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use fs_err::{self as fs, File};
+use npyz::{NpyWriter, WriterBuilder};
+use shakmaty::Position;
+
+use super::{already_exists, OutputBackend, OutputLabel};
+use crate::{
+    chess_to_planes,
+    features::chess_to_features,
+    history::history_to_planes,
+    input_to_sparse, legal_move_mask,
+    manifest::{Encoding, Layout, Manifest, Packing},
+    move_output_count, nnue, pack_bits, pack_input,
+    variant::position_to_variant_inputs,
+    Sample, INPUT_LENGTH,
+};
+
+/// Writes paired `<prefix>_input/<chunk>.npy` and `<prefix>_output/<chunk>.npy` files.
+///
+/// Legal move masks, history planes and soft policy targets are written to `<prefix>_legal`,
+/// `<prefix>_history`, `<prefix>_policy_moves` and `<prefix>_policy_probs` if they are enabled.
+/// NNUE encodings also write the side to move to `<prefix>_turn`, variants with pockets or
+/// check counters write them to `<prefix>_variant`.
+pub struct NpyBackend<T: OutputLabel> {
+    dir: PathBuf,
+    prefix: String,
+    manifest: Manifest,
+    writers: Option<ChunkWriters<T>>,
+}
+
+struct ChunkWriters<T: OutputLabel> {
+    inputs: InputWriter<File>,
+    outputs: NpyWriter<T, File>,
+    turn: Option<NpyWriter<bool, File>>,
+    legal_moves: Option<NpyWriter<u8, File>>,
+    history: Option<NpyWriter<bool, File>>,
+    variant: Option<NpyWriter<u8, File>>,
+    policy: Option<(NpyWriter<u16, File>, NpyWriter<f32, File>)>,
+}
+
+impl<T: OutputLabel> NpyBackend<T> {
+    pub fn create(dir: &Path, prefix: &str, manifest: &Manifest) -> io::Result<Self> {
+        let mut arrays = vec!["input", "output"];
+        if manifest.encoding != Encoding::Board {
+            arrays.push("turn");
+        }
+        if manifest.legal_moves {
+            arrays.push("legal");
+        }
+        if manifest.history.is_some() {
+            arrays.push("history");
+        }
+        if manifest.variant_inputs.is_some() {
+            arrays.push("variant");
+        }
+        if manifest.policy_top_k.is_some() {
+            arrays.extend(["policy_moves", "policy_probs"]);
+        }
+        let dirs: Vec<_> = arrays
+            .into_iter()
+            .map(|array| array_dir(dir, prefix, array))
+            .collect();
+
+        for dir in &dirs {
+            if dir.try_exists()? {
+                return Err(already_exists(dir));
+            }
+        }
+
+        for dir in &dirs {
+            fs::create_dir(dir)?;
+        }
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            prefix: prefix.to_owned(),
+            manifest: manifest.clone(),
+            writers: None,
+        })
+    }
+
+    fn create_file(&self, array: &str, chunk_index: usize) -> io::Result<File> {
+        File::create(array_dir(&self.dir, &self.prefix, array).join(format!("{chunk_index}.npy")))
+    }
+}
+
+impl<T: OutputLabel> OutputBackend<T> for NpyBackend<T> {
+    fn begin_chunk(&mut self, chunk_index: usize) -> io::Result<()> {
+        let inputs = InputWriter::new(self.create_file("input", chunk_index)?, &self.manifest)?;
+
+        let outputs = npyz::WriteOptions::new()
+            .default_dtype()
+            .shape(&[self.manifest.boards_per_file as u64])
+            .writer(self.create_file("output", chunk_index)?)
+            .begin_nd()?;
+
+        let turn = match self.manifest.encoding {
+            Encoding::Board => None,
+            Encoding::HalfKp | Encoding::HalfKaV2 => Some(turn_writer(
+                self.create_file("turn", chunk_index)?,
+                &self.manifest,
+            )?),
+        };
+
+        let legal_moves = match self.manifest.legal_moves {
+            true => Some(legal_moves_writer(
+                self.create_file("legal", chunk_index)?,
+                &self.manifest,
+            )?),
+            false => None,
+        };
+
+        let history = match self.manifest.history {
+            Some(_) => Some(history_writer(
+                self.create_file("history", chunk_index)?,
+                &self.manifest,
+            )?),
+            None => None,
+        };
+
+        let variant = match self.manifest.variant_inputs {
+            Some(_) => Some(variant_writer(
+                self.create_file("variant", chunk_index)?,
+                &self.manifest,
+            )?),
+            None => None,
+        };
+
+        let policy = match self.manifest.policy_top_k {
+            Some(_) => Some((
+                policy_writer(
+                    self.create_file("policy_moves", chunk_index)?,
+                    &self.manifest,
+                )?,
+                policy_writer(
+                    self.create_file("policy_probs", chunk_index)?,
+                    &self.manifest,
+                )?,
+            )),
+            None => None,
+        };
+
+        self.writers = Some(ChunkWriters {
+            inputs,
+            outputs,
+            turn,
+            legal_moves,
+            history,
+            variant,
+            policy,
+        });
+        Ok(())
+    }
+
+    fn push(&mut self, input: &[bool; INPUT_LENGTH], sample: &Sample<T>) -> io::Result<()> {
+        let writers = self.writers.as_mut().expect("chunk not started");
+        writers.inputs.push(input, &sample.chess)?;
+        if let Some(writer) = &mut writers.turn {
+            writer.push(&sample.chess.turn().is_white())?;
+        }
+        if let Some(writer) = &mut writers.legal_moves {
+            writer.extend(legal_move_mask(&sample.chess, self.manifest.castling))?;
+        }
+        if let Some(writer) = &mut writers.history {
+            writer.extend(history_values(sample, &self.manifest))?;
+        }
+        if let Some(writer) = &mut writers.variant {
+            writer.extend(position_to_variant_inputs(&sample.chess))?;
+        }
+        if let (Some((moves, probabilities)), Some(top_k)) =
+            (&mut writers.policy, self.manifest.policy_top_k)
+        {
+            let (policy_moves, policy_probabilities) = pad_policy(sample, top_k);
+            moves.extend(policy_moves)?;
+            probabilities.extend(policy_probabilities)?;
+        }
+        writers.outputs.push(&sample.label)
+    }
+
+    fn finish_chunk(&mut self) -> io::Result<()> {
+        let writers = self.writers.take().expect("chunk not started");
+        writers.inputs.finish()?;
+        if let Some(writer) = writers.turn {
+            writer.finish()?;
+        }
+        if let Some(writer) = writers.legal_moves {
+            writer.finish()?;
+        }
+        if let Some(writer) = writers.history {
+            writer.finish()?;
+        }
+        if let Some(writer) = writers.variant {
+            writer.finish()?;
+        }
+        if let Some((moves, probabilities)) = writers.policy {
+            moves.finish()?;
+            probabilities.finish()?;
+        }
+        writers.outputs.finish()
+    }
+}
+
+/// The directory of one of the arrays of an npy dataset, e.g. `<prefix>_input`.
+fn array_dir(dir: &Path, prefix: &str, array: &str) -> PathBuf {
+    dir.join(format!("{prefix}_{array}"))
+}
+
+/// Creates the writer for the `(N,)` side to move of one chunk, `true` for White.
+pub(super) fn turn_writer<W: io::Write>(
+    writer: W,
+    manifest: &Manifest,
+) -> io::Result<NpyWriter<bool, W>> {
+    npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(&[manifest.boards_per_file as u64])
+        .writer(writer)
+        .begin_nd()
+}
+
+/// Creates the writer for the `(N, move_output_count / 8)` legal move masks of one chunk.
+pub(super) fn legal_moves_writer<W: io::Write>(
+    writer: W,
+    manifest: &Manifest,
+) -> io::Result<NpyWriter<u8, W>> {
+    npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(&[
+            manifest.boards_per_file as u64,
+            move_output_count(manifest.variant) as u64 / 8,
+        ])
+        .writer(writer)
+        .begin_nd()
+}
+
+/// Creates the writer for the `(N, variant_inputs)` pocket counts or remaining checks of one
+/// chunk.
+pub(super) fn variant_writer<W: io::Write>(
+    writer: W,
+    manifest: &Manifest,
+) -> io::Result<NpyWriter<u8, W>> {
+    let inputs = manifest.variant_inputs.as_ref().expect("variant inputs");
+    npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(&[manifest.boards_per_file as u64, inputs.len() as u64])
+        .writer(writer)
+        .begin_nd()
+}
+
+/// Creates the writer for the history planes of one chunk, shaped like
+/// [`Manifest::history_shape`].
+pub(super) fn history_writer<W: io::Write>(
+    writer: W,
+    manifest: &Manifest,
+) -> io::Result<NpyWriter<bool, W>> {
+    let mut shape = vec![manifest.boards_per_file as u64];
+    shape.extend(manifest.history_shape().expect("history enabled"));
+    npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(&shape)
+        .writer(writer)
+        .begin_nd()
+}
+
+/// The history planes of a sample in the order of the manifest's layout. Samples without a
+/// history, e.g. from puzzles, get empty planes.
+pub(super) fn history_values<T>(sample: &Sample<T>, manifest: &Manifest) -> Vec<bool> {
+    let count = manifest.history.as_ref().map_or(0, Vec::len);
+    let history = sample.history.clone().unwrap_or_default();
+    layout_planes(&history_to_planes(&history, count), manifest.layout).collect()
+}
+
+/// Iterates over the values of planes in the order of the layout: plane by plane, or square by
+/// square for [`Layout::PlanesLast`].
+fn layout_planes(planes: &[[bool; 64]], layout: Layout) -> impl Iterator<Item = bool> + '_ {
+    let count = planes.len();
+    (0..count * 64).map(move |index| match layout {
+        Layout::PlanesLast => planes[index % count][index / count],
+        _ => planes[index / 64][index % 64],
+    })
+}
+
+/// Creates the writer for the `(N, policy_top_k)` moves or probabilities of one chunk.
+pub(super) fn policy_writer<T: npyz::AutoSerialize, W: io::Write>(
+    writer: W,
+    manifest: &Manifest,
+) -> io::Result<NpyWriter<T, W>> {
+    let top_k = manifest.policy_top_k.expect("policy enabled");
+    npyz::WriteOptions::new()
+        .default_dtype()
+        .shape(&[manifest.boards_per_file as u64, top_k as u64])
+        .writer(writer)
+        .begin_nd()
+}
+
+/// The policy of a sample as `top_k` moves and probabilities, padded with move 0 and
+/// probability 0.
+pub(super) fn pad_policy<T>(sample: &Sample<T>, top_k: usize) -> (Vec<u16>, Vec<f32>) {
+    let policy = sample.policy.as_deref().unwrap_or_default();
+    let mut moves = vec![0; top_k];
+    let mut probabilities = vec![0.0; top_k];
+    for (index, &(m, probability)) in policy.iter().take(top_k).enumerate() {
+        moves[index] = m;
+        probabilities[index] = probability;
+    }
+    (moves, probabilities)
+}
+
+/// Writes the inputs of one chunk, either as `bool`s, bit-packed, as sparse indices or as planes.
+///
+/// The `bool` marks whether the tactical feature planes are appended. NNUE encodings are written
+/// as feature indices instead.
+pub(super) enum InputWriter<W: io::Write> {
+    Dense(NpyWriter<bool, W>, bool),
+    Packed(NpyWriter<u8, W>, bool),
+    Sparse(NpyWriter<i16, W>),
+    Planes(NpyWriter<bool, W>, Layout, bool),
+    Nnue(NpyWriter<i32, W>, Encoding),
+}
+
+impl<W: io::Write> InputWriter<W> {
+    pub(super) fn new(writer: W, manifest: &Manifest) -> io::Result<Self> {
+        let features = manifest.features.is_some();
+        let mut shape = vec![manifest.boards_per_file as u64];
+        shape.extend(manifest.input_shape());
+        if manifest.encoding != Encoding::Board {
+            return Ok(InputWriter::Nnue(
+                npyz::WriteOptions::new()
+                    .default_dtype()
+                    .shape(&shape)
+                    .writer(writer)
+                    .begin_nd()?,
+                manifest.encoding,
+            ));
+        }
+        if manifest.layout != Layout::Flat {
+            return Ok(InputWriter::Planes(
+                npyz::WriteOptions::new()
+                    .default_dtype()
+                    .shape(&shape)
+                    .writer(writer)
+                    .begin_nd()?,
+                manifest.layout,
+                features,
+            ));
+        }
+        Ok(match manifest.packing {
+            Packing::None => InputWriter::Dense(
+                npyz::WriteOptions::new()
+                    .default_dtype()
+                    .shape(&shape)
+                    .writer(writer)
+                    .begin_nd()?,
+                features,
+            ),
+            Packing::Packbits => InputWriter::Packed(
+                npyz::WriteOptions::new()
+                    .default_dtype()
+                    .shape(&shape)
+                    .writer(writer)
+                    .begin_nd()?,
+                features,
+            ),
+            Packing::Sparse => InputWriter::Sparse(
+                npyz::WriteOptions::new()
+                    .default_dtype()
+                    .shape(&shape)
+                    .writer(writer)
+                    .begin_nd()?,
+            ),
+        })
+    }
+
+    pub(super) fn push(
+        &mut self,
+        input: &[bool; INPUT_LENGTH],
+        chess: &impl Position,
+    ) -> io::Result<()> {
+        match self {
+            InputWriter::Dense(writer, false) => writer.extend(input.iter().copied()),
+            InputWriter::Dense(writer, true) => {
+                writer.extend(input.iter().copied())?;
+                writer.extend(chess_to_features(chess).into_iter().flatten())
+            }
+            InputWriter::Packed(writer, false) => writer.extend(pack_input(input)),
+            InputWriter::Packed(writer, true) => {
+                let features = chess_to_features(chess);
+                let values: Vec<_> = input
+                    .iter()
+                    .chain(features.iter().flatten())
+                    .copied()
+                    .collect();
+                writer.extend(pack_bits(&values))
+            }
+            InputWriter::Sparse(writer) => writer.extend(input_to_sparse(input)),
+            InputWriter::Planes(writer, layout, features) => {
+                let mut planes = chess_to_planes(chess).to_vec();
+                if *features {
+                    planes.extend(chess_to_features(chess));
+                }
+                writer.extend(layout_planes(&planes, *layout))
+            }
+            InputWriter::Nnue(writer, encoding) => writer.extend(
+                nnue::chess_to_features(chess, *encoding)
+                    .into_iter()
+                    .flatten(),
+            ),
+        }
+    }
+
+    pub(super) fn finish(self) -> io::Result<()> {
+        match self {
+            InputWriter::Dense(writer, _) => writer.finish(),
+            InputWriter::Packed(writer, _) => writer.finish(),
+            InputWriter::Sparse(writer) => writer.finish(),
+            InputWriter::Planes(writer, _, _) => writer.finish(),
+            InputWriter::Nnue(writer, _) => writer.finish(),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufWriter, Cursor, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use fs_err::{self as fs, File};
+use npyz::{npz, WriterBuilder};
+use shakmaty::Position;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{
+    already_exists,
+    npy::{
+        history_values, history_writer, legal_moves_writer, pad_policy, policy_writer, turn_writer,
+        variant_writer, InputWriter,
+    },
+    OutputBackend, OutputLabel,
+};
+use crate::{
+    dataset::chunk_extension,
+    legal_move_mask,
+    manifest::{Compression, Encoding, Manifest},
+    variant::position_to_variant_inputs,
+    Sample, INPUT_LENGTH,
+};
+
+/// An upper bound of the size of an npy header.
+const NPY_HEADER_MAX: usize = 4096;
+
+/// Writes one `<prefix>_npz/<chunk>.npz` archive per chunk, loadable with `np.load`.
+///
+/// The inputs are streamed into the archive. The labels, the side to move of NNUE encodings
+/// (`turn`), legal move masks (`legal`), history planes (`history`), variant inputs
+/// (`variant`) and soft policy targets (`policy_moves` and `policy_probs`) are kept in memory
+/// until the chunk is finished. With zstd compression, the whole archive is built in memory and
+/// then compressed.
+pub struct NpzBackend<T: OutputLabel> {
+    dir: PathBuf,
+    manifest: Manifest,
+    chunk: Option<Chunk<T>>,
+}
+
+struct Chunk<T> {
+    index: usize,
+    archive: SharedArchive,
+    inputs: InputWriter<SharedArchive>,
+    labels: Vec<T>,
+    turn: Option<Vec<bool>>,
+    legal_moves: Option<Vec<u8>>,
+    history: Option<Vec<bool>>,
+    variant: Option<Vec<u8>>,
+    policy: Option<(Vec<u16>, Vec<f32>)>,
+}
+
+impl<T: OutputLabel> NpzBackend<T> {
+    pub fn create(dir: &Path, prefix: &str, manifest: &Manifest) -> io::Result<Self> {
+        let dir = dir.join(format!("{prefix}_npz"));
+
+        if dir.try_exists()? {
+            return Err(already_exists(&dir));
+        }
+        fs::create_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            manifest: manifest.clone(),
+            chunk: None,
+        })
+    }
+
+    fn chunk_path(&self, chunk_index: usize) -> PathBuf {
+        self.dir
+            .join(format!("{chunk_index}.{}", chunk_extension(&self.manifest)))
+    }
+
+    /// The options of an archive entry with `size` bytes of array data.
+    fn file_options(&self, size: usize) -> FileOptions {
+        let method = match self.manifest.compression {
+            Compression::Deflate => CompressionMethod::Deflated,
+            _ => CompressionMethod::Stored,
+        };
+        FileOptions::default()
+            .compression_method(method)
+            .large_file(needs_zip64(size))
+    }
+}
+
+/// Entries of 4 GiB and more need the zip64 format. Leaves room for the npy header.
+fn needs_zip64(size: usize) -> bool {
+    size + NPY_HEADER_MAX >= u32::MAX as usize
+}
+
+impl<T: OutputLabel> OutputBackend<T> for NpzBackend<T> {
+    fn begin_chunk(&mut self, chunk_index: usize) -> io::Result<()> {
+        let target = match self.manifest.compression {
+            Compression::Zstd => ArchiveTarget::Memory(Cursor::new(Vec::new())),
+            _ => ArchiveTarget::File(BufWriter::new(File::create(self.chunk_path(chunk_index))?)),
+        };
+        let archive = SharedArchive(Rc::new(RefCell::new(ZipWriter::new(target))));
+        // Sparse inputs take two bytes per value.
+        let input_size = self.manifest.boards_per_file * self.manifest.input_columns() * 2;
+        archive.0.borrow_mut().start_file(
+            npz::file_name_from_array_name("input"),
+            self.file_options(input_size),
+        )?;
+        let inputs = InputWriter::new(archive.clone(), &self.manifest)?;
+
+        self.chunk = Some(Chunk {
+            index: chunk_index,
+            archive,
+            inputs,
+            labels: Vec::with_capacity(self.manifest.boards_per_file),
+            turn: (self.manifest.encoding != Encoding::Board).then(Vec::new),
+            legal_moves: self.manifest.legal_moves.then(Vec::new),
+            history: self.manifest.history.as_ref().map(|_| Vec::new()),
+            variant: self.manifest.variant_inputs.as_ref().map(|_| Vec::new()),
+            policy: self.manifest.policy_top_k.map(|_| Default::default()),
+        });
+        Ok(())
+    }
+
+    fn push(&mut self, input: &[bool; INPUT_LENGTH], sample: &Sample<T>) -> io::Result<()> {
+        let chunk = self.chunk.as_mut().expect("chunk not started");
+        chunk.inputs.push(input, &sample.chess)?;
+        chunk.labels.push(sample.label);
+        if let Some(turn) = &mut chunk.turn {
+            turn.push(sample.chess.turn().is_white());
+        }
+        if let Some(legal_moves) = &mut chunk.legal_moves {
+            legal_moves.extend(legal_move_mask(&sample.chess, self.manifest.castling));
+        }
+        if let Some(history) = &mut chunk.history {
+            history.extend(history_values(sample, &self.manifest));
+        }
+        if let Some(variant) = &mut chunk.variant {
+            variant.extend(position_to_variant_inputs(&sample.chess));
+        }
+        if let (Some((moves, probabilities)), Some(top_k)) =
+            (&mut chunk.policy, self.manifest.policy_top_k)
+        {
+            let (policy_moves, policy_probabilities) = pad_policy(sample, top_k);
+            moves.extend(policy_moves);
+            probabilities.extend(policy_probabilities);
+        }
+        Ok(())
+    }
+
+    fn finish_chunk(&mut self) -> io::Result<()> {
+        let chunk = self.chunk.take().expect("chunk not started");
+        chunk.inputs.finish()?;
+
+        let mut zip = Rc::try_unwrap(chunk.archive.0)
+            .ok()
+            .expect("input writer finished")
+            .into_inner();
+        zip.start_file(
+            npz::file_name_from_array_name("output"),
+            self.file_options(chunk.labels.len() * mem::size_of::<T>()),
+        )?;
+        let mut outputs = npyz::WriteOptions::new()
+            .default_dtype()
+            .shape(&[self.manifest.boards_per_file as u64])
+            .writer(&mut zip)
+            .begin_nd()?;
+        outputs.extend(chunk.labels)?;
+        outputs.finish()?;
+
+        if let Some(turn) = chunk.turn {
+            zip.start_file(
+                npz::file_name_from_array_name("turn"),
+                self.file_options(turn.len()),
+            )?;
+            let mut writer = turn_writer(&mut zip, &self.manifest)?;
+            writer.extend(turn)?;
+            writer.finish()?;
+        }
+
+        if let Some(legal_moves) = chunk.legal_moves {
+            zip.start_file(
+                npz::file_name_from_array_name("legal"),
+                self.file_options(legal_moves.len()),
+            )?;
+            let mut writer = legal_moves_writer(&mut zip, &self.manifest)?;
+            writer.extend(legal_moves)?;
+            writer.finish()?;
+        }
+
+        if let Some(history) = chunk.history {
+            zip.start_file(
+                npz::file_name_from_array_name("history"),
+                self.file_options(history.len()),
+            )?;
+            let mut writer = history_writer(&mut zip, &self.manifest)?;
+            writer.extend(history)?;
+            writer.finish()?;
+        }
+
+        if let Some(variant) = chunk.variant {
+            zip.start_file(
+                npz::file_name_from_array_name("variant"),
+                self.file_options(variant.len()),
+            )?;
+            let mut writer = variant_writer(&mut zip, &self.manifest)?;
+            writer.extend(variant)?;
+            writer.finish()?;
+        }
+
+        if let Some((moves, probabilities)) = chunk.policy {
+            zip.start_file(
+                npz::file_name_from_array_name("policy_moves"),
+                self.file_options(moves.len() * mem::size_of::<u16>()),
+            )?;
+            let mut writer = policy_writer(&mut zip, &self.manifest)?;
+            writer.extend(moves)?;
+            writer.finish()?;
+
+            zip.start_file(
+                npz::file_name_from_array_name("policy_probs"),
+                self.file_options(probabilities.len() * mem::size_of::<f32>()),
+            )?;
+            let mut writer = policy_writer(&mut zip, &self.manifest)?;
+            writer.extend(probabilities)?;
+            writer.finish()?;
+        }
+
+        match zip.finish()? {
+            ArchiveTarget::File(mut file) => file.flush(),
+            ArchiveTarget::Memory(archive) => {
+                let file = File::create(self.chunk_path(chunk.index))?;
+                zstd::stream::copy_encode(archive.get_ref().as_slice(), file, 0)
+            }
+        }
+    }
+}
+
+/// The zip writer of a chunk, shared between the input writer and the backend.
+#[derive(Clone)]
+struct SharedArchive(Rc<RefCell<ZipWriter<ArchiveTarget>>>);
+
+impl Write for SharedArchive {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// An archive is written to its file directly, or to memory if it is compressed afterwards.
+enum ArchiveTarget {
+    File(BufWriter<File>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Write for ArchiveTarget {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveTarget::File(file) => file.write(buf),
+            ArchiveTarget::Memory(cursor) => cursor.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveTarget::File(file) => file.flush(),
+            ArchiveTarget::Memory(cursor) => cursor.flush(),
+        }
+    }
+}
+
+impl Seek for ArchiveTarget {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ArchiveTarget::File(file) => file.seek(pos),
+            ArchiveTarget::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use shakmaty::Chess;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::{
+        chess_to_input,
+        dataset::{open_arrays, ChunkReader, Label},
+        manifest::Format,
+        move_to_output, DatasetConfig, DatasetWriter, MOVE_OUTPUTS,
+    };
+
+    #[test]
+    fn zip64_only_for_entries_of_4_gib() {
+        assert!(!needs_zip64(0));
+        assert!(!needs_zip64(u32::MAX as usize - NPY_HEADER_MAX - 1));
+        assert!(needs_zip64(u32::MAX as usize - NPY_HEADER_MAX));
+        assert!(needs_zip64(5 << 30));
+    }
+
+    #[test]
+    fn archives_read_back_with_every_compression() {
+        // The starting position and the positions after three of its moves.
+        let start = Chess::default();
+        let samples = [(start.clone(), start.legal_moves()[0].clone())]
+            .into_iter()
+            .chain(start.legal_moves().into_iter().take(3).map(|m| {
+                let chess = start.clone().play(&m).expect("legal");
+                let reply = chess.legal_moves()[0].clone();
+                (chess, reply)
+            }))
+            .collect::<Vec<_>>();
+
+        for compression in [Compression::None, Compression::Deflate, Compression::Zstd] {
+            let dir = env::temp_dir().join(format!(
+                "neural-chess-npz-{compression:?}-{}",
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).expect("temporary directory");
+
+            let manifest = DatasetWriter::builder("archive")
+                .output_dir(&dir)
+                .config(DatasetConfig {
+                    total: 4,
+                    boards_per_file: 4,
+                    format: Format::Npz,
+                    compression,
+                    legal_moves: true,
+                    ..DatasetConfig::default()
+                })
+                .build()
+                .expect("valid config")
+                .write_moves(
+                    samples
+                        .iter()
+                        .map(|(chess, m)| Sample::new(chess.clone(), m.clone())),
+                )
+                .expect("dataset written");
+
+            let rows = ChunkReader::open(&dir, "archive", 0)
+                .expect("chunk")
+                .collect::<io::Result<Vec<_>>>()
+                .expect("rows");
+            let legal = open_arrays(&dir, "archive", 0, &manifest, &["legal"])
+                .expect("legal move masks")
+                .remove(0);
+            assert_eq!(legal.shape(), [4, MOVE_OUTPUTS as u64 / 8]);
+            if compression == Compression::Deflate {
+                let archive = fs::read(dir.join("archive_npz/0.npz")).expect("archive");
+                let mut archive = ZipArchive::new(Cursor::new(archive)).expect("zip archive");
+                let entry = archive.by_name("input.npy").expect("input entry");
+                assert_eq!(entry.compression(), CompressionMethod::Deflated);
+            }
+            fs::remove_dir_all(&dir).expect("temporary directory");
+
+            assert_eq!(rows.len(), 4);
+            for ((input, label), (chess, m)) in rows.into_iter().zip(&samples) {
+                assert_eq!(input, chess_to_input(chess));
+                assert_eq!(label, Label::Move(move_to_output(m)));
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression as GzCompression};
+use fs_err::{self as fs, File};
+
+use super::{already_exists, OutputBackend, OutputLabel};
+use crate::{
+    dataset::{chunk_extension, Label},
+    manifest::{Compression, Manifest},
+    pack_input, Sample, INPUT_LENGTH,
+};
+
+/// Writes one `<prefix>_tfrecord/<chunk>.tfrecord` file per chunk, for `tf.data.TFRecordDataset`.
+///
+/// Every record is a `tf.train.Example` with the bit-packed `input` as bytes and either a `move`
+/// (int64) or an `eval` (float) feature.
+pub struct TfrecordBackend {
+    dir: PathBuf,
+    manifest: Manifest,
+    file: Option<RecordFile>,
+    record: Vec<u8>,
+}
+
+impl TfrecordBackend {
+    pub fn create(dir: &Path, prefix: &str, manifest: &Manifest) -> io::Result<Self> {
+        let dir = dir.join(format!("{prefix}_tfrecord"));
+
+        if dir.try_exists()? {
+            return Err(already_exists(&dir));
+        }
+        fs::create_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            manifest: manifest.clone(),
+            file: None,
+            record: Vec::new(),
+        })
+    }
+}
+
+impl<T: OutputLabel> OutputBackend<T> for TfrecordBackend {
+    fn begin_chunk(&mut self, chunk_index: usize) -> io::Result<()> {
+        let path = self
+            .dir
+            .join(format!("{chunk_index}.{}", chunk_extension(&self.manifest)));
+        let file = BufWriter::new(File::create(path)?);
+        self.file = Some(match self.manifest.compression {
+            Compression::Gzip => RecordFile::Gzip(GzEncoder::new(file, GzCompression::default())),
+            _ => RecordFile::Plain(file),
+        });
+        Ok(())
+    }
+
+    fn push(&mut self, input: &[bool; INPUT_LENGTH], sample: &Sample<T>) -> io::Result<()> {
+        let file = self.file.as_mut().expect("chunk not started");
+
+        self.record.clear();
+        encode_example(&mut self.record, input, sample.label.into());
+        write_record(file, &self.record)
+    }
+
+    fn finish_chunk(&mut self) -> io::Result<()> {
+        match self.file.take().expect("chunk not started") {
+            RecordFile::Plain(mut file) => file.flush(),
+            RecordFile::Gzip(file) => file.finish()?.flush(),
+        }
+    }
+}
+
+enum RecordFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Write for RecordFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RecordFile::Plain(file) => file.write(buf),
+            RecordFile::Gzip(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordFile::Plain(file) => file.flush(),
+            RecordFile::Gzip(file) => file.flush(),
+        }
+    }
+}
+
+/// Frames a record as `length, masked crc of length, data, masked crc of data`.
+fn write_record(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let length = (data.len() as u64).to_le_bytes();
+    writer.write_all(&length)?;
+    writer.write_all(&masked_crc(&length).to_le_bytes())?;
+    writer.write_all(data)?;
+    writer.write_all(&masked_crc(data).to_le_bytes())
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = crc32c::crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+/// Encodes a `tf.train.Example` protobuf with the `input` and label features.
+fn encode_example(buf: &mut Vec<u8>, input: &[bool; INPUT_LENGTH], label: Label) {
+    // BytesList { repeated bytes value = 1 }
+    let mut bytes_list = Vec::new();
+    encode_bytes(&mut bytes_list, 1, &pack_input(input));
+
+    // Int64List / FloatList { repeated value = 1 [packed = true] }
+    let (name, list_field, list) = match label {
+        Label::Move(index) => {
+            let mut value = Vec::new();
+            encode_varint(&mut value, index.into());
+            ("move", 3, value)
+        }
+        Label::Eval(eval) => ("eval", 2, eval.to_le_bytes().to_vec()),
+    };
+    let mut label_list = Vec::new();
+    encode_bytes(&mut label_list, 1, &list);
+
+    // Features { map<string, Feature> feature = 1 }
+    let mut features = Vec::new();
+    for (name, list_field, list) in [("input", 1, bytes_list), (name, list_field, label_list)] {
+        // Feature { oneof kind { BytesList = 1, FloatList = 2, Int64List = 3 } }
+        let mut feature = Vec::new();
+        encode_bytes(&mut feature, list_field, &list);
+
+        let mut entry = Vec::new();
+        encode_bytes(&mut entry, 1, name.as_bytes());
+        encode_bytes(&mut entry, 2, &feature);
+        encode_bytes(&mut features, 1, &entry);
+    }
+
+    // Example { Features features = 1 }
+    encode_bytes(buf, 1, &features);
+}
+
+/// Encodes a length-delimited field.
+fn encode_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    encode_varint(buf, field << 3 | 2);
+    encode_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Read};
+
+    use flate2::read::GzDecoder;
+    use shakmaty::{Chess, Position};
+
+    use super::*;
+    use crate::{chess_to_input, manifest::Format, DatasetConfig, DatasetWriter};
+
+    /// Splits framed records, checking their lengths and checksums.
+    fn read_records(mut data: &[u8]) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        while !data.is_empty() {
+            let (length, rest) = data.split_at(8);
+            let (length_crc, rest) = rest.split_at(4);
+            assert_eq!(length_crc, masked_crc(length).to_le_bytes());
+            let length = u64::from_le_bytes(length.try_into().unwrap()) as usize;
+            let (record, rest) = rest.split_at(length);
+            let (record_crc, rest) = rest.split_at(4);
+            assert_eq!(record_crc, masked_crc(record).to_le_bytes());
+            records.push(record.to_vec());
+            data = rest;
+        }
+        records
+    }
+
+    #[test]
+    fn crc_is_masked_like_tensorflow() {
+        // The check value of CRC-32C is 0xe3069283.
+        assert_eq!(crc32c::crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(masked_crc(b"123456789"), 0xc78a_b0e5);
+        assert_eq!(masked_crc(b""), 0xa282_ead8);
+    }
+
+    #[test]
+    fn varints_and_examples() {
+        let mut buf = Vec::new();
+        encode_varint(&mut buf, 1);
+        encode_varint(&mut buf, 300);
+        assert_eq!(buf, [0x01, 0xac, 0x02]);
+
+        let input = chess_to_input(&Chess::default());
+        let mut example = Vec::new();
+        encode_example(&mut example, &input, Label::Move(12 * 64 + 28));
+        // The move feature comes last: key "move", Int64List with the packed varint 796.
+        let mut entry = vec![0x0a, 4];
+        entry.extend_from_slice(b"move");
+        entry.extend_from_slice(&[0x12, 6, 0x1a, 4, 0x0a, 2, 0x9c, 0x06]);
+        assert!(example.ends_with(&entry));
+        assert_eq!(example[0], 0x0a);
+        let packed = pack_input(&input);
+        assert!(example
+            .windows(packed.len())
+            .any(|window| window == packed.as_slice()));
+
+        let mut example = Vec::new();
+        encode_example(&mut example, &input, Label::Eval(0.5));
+        let mut entry = vec![0x0a, 4];
+        entry.extend_from_slice(b"eval");
+        entry.extend_from_slice(&[0x12, 8, 0x12, 6, 0x0a, 4]);
+        entry.extend_from_slice(&0.5f32.to_le_bytes());
+        assert!(example.ends_with(&entry));
+    }
+
+    #[test]
+    fn gzip_chunks_hold_one_record_per_position() {
+        let dir = env::temp_dir().join(format!("neural-chess-tfrecord-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temporary directory");
+
+        let start = Chess::default();
+        let samples = start
+            .legal_moves()
+            .into_iter()
+            .take(2)
+            .map(|m| Sample::new(start.clone().play(&m).expect("legal"), 0.5_f32));
+        DatasetWriter::builder("records")
+            .output_dir(&dir)
+            .config(DatasetConfig {
+                total: 2,
+                boards_per_file: 2,
+                format: Format::Tfrecord,
+                compression: Compression::Gzip,
+                ..DatasetConfig::default()
+            })
+            .build()
+            .expect("valid config")
+            .write(samples)
+            .expect("dataset written");
+
+        let mut data = Vec::new();
+        GzDecoder::new(File::open(dir.join("records_tfrecord/0.tfrecord.gz")).expect("chunk"))
+            .read_to_end(&mut data)
+            .expect("gzip stream");
+        fs::remove_dir_all(&dir).expect("temporary directory");
+
+        let records = read_records(&data);
+        assert_eq!(records.len(), 2);
+        for record in records {
+            assert!(record.ends_with(&0.5f32.to_le_bytes()));
+        }
+    }
+}
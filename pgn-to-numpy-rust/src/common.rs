@@ -1,22 +1,55 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    fmt::Debug,
-    hash::{Hash, Hasher},
-    io::{self, Write},
-    path::Path,
-    time::Instant,
-};
+use std::sync::Arc;
 
-use fs_err::{self as fs, File};
 use itertools::Itertools;
-use npyz::WriterBuilder;
-use shakmaty::{Chess, Color, Move, Piece, Position, Square};
+use shakmaty::{
+    uci::Uci, variant::VariantPosition, Bitboard, Board, CastlingMode, CastlingSide, Chess, Color,
+    EnPassantMode, File as ChessFile, FromSetup, Move, Piece, Position, PositionError, Rank, Role,
+    Setup, Square,
+};
 
-use crate::ARGS;
+use crate::{
+    history::History,
+    manifest::{Castling, Variant},
+    policy::Policy,
+    variant::DROP_ROLES,
+};
 
 pub const INPUT_LENGTH: usize = 1 + (1 + 2 * 6) * 64;
+pub const PACKED_INPUT_LENGTH: usize = INPUT_LENGTH.div_ceil(8);
+/// The side to move and one value per square are the only values that can be set.
+pub const SPARSE_INPUT_LENGTH: usize = 1 + 64;
+
+/// The number of move indices `from * 64 + to` of [`move_to_output`].
+pub const MOVE_OUTPUTS: usize = 64 * 64;
+/// Crazyhouse drops follow the other moves, 64 target squares for each of the [`DROP_ROLES`].
+pub const DROP_MOVE_OUTPUTS: usize = MOVE_OUTPUTS + DROP_ROLES.len() * 64;
+
+/// One bit per move index of [`move_to_output`], packed like [`pack_input`].
+pub const LEGAL_MOVE_MASK_LENGTH: usize = MOVE_OUTPUTS / 8;
 
-pub fn chess_to_input(chess: &Chess) -> [bool; INPUT_LENGTH] {
+/// The planes written by [`chess_to_planes`], in order.
+pub const PLANES: [&str; 2 * 6 + 1 + 4 + 1] = [
+    "white-pawn",
+    "white-knight",
+    "white-bishop",
+    "white-rook",
+    "white-queen",
+    "white-king",
+    "black-pawn",
+    "black-knight",
+    "black-bishop",
+    "black-rook",
+    "black-queen",
+    "black-king",
+    "white-to-move",
+    "white-kingside-castling",
+    "white-queenside-castling",
+    "black-kingside-castling",
+    "black-queenside-castling",
+    "en-passant",
+];
+
+pub fn chess_to_input(chess: &impl Position) -> [bool; INPUT_LENGTH] {
     let mut output = [false; INPUT_LENGTH];
 
     output[0] = chess.turn().is_white();
@@ -39,151 +72,565 @@ pub fn chess_to_input(chess: &Chess) -> [bool; INPUT_LENGTH] {
     output
 }
 
+/// Packs eight input values into each byte, most significant bit first, like `np.packbits`.
+pub fn pack_input(input: &[bool; INPUT_LENGTH]) -> [u8; PACKED_INPUT_LENGTH] {
+    pack_bits(input).try_into().expect("packed input length")
+}
+
+/// Like [`pack_input`], for any number of values. The last byte is padded with zeros.
+pub fn pack_bits(values: &[bool]) -> Vec<u8> {
+    values
+        .chunks(8)
+        .map(|bits| {
+            bits.iter()
+                .enumerate()
+                .fold(0, |byte, (bit, &value)| byte | u8::from(value) << (7 - bit))
+        })
+        .collect()
+}
+
+/// The inverse of [`pack_input`], like `np.unpackbits(packed, count=INPUT_LENGTH)`.
+pub fn unpack_input(packed: &[u8]) -> [bool; INPUT_LENGTH] {
+    let mut input = [false; INPUT_LENGTH];
+    for (index, value) in input.iter_mut().enumerate() {
+        *value = packed[index / 8] & (1 << (7 - index % 8)) != 0;
+    }
+    input
+}
+
+/// Lists the indices of the set input values, padded with `-1`.
+pub fn input_to_sparse(input: &[bool; INPUT_LENGTH]) -> [i16; SPARSE_INPUT_LENGTH] {
+    let mut sparse = [-1; SPARSE_INPUT_LENGTH];
+    for (slot, index) in sparse
+        .iter_mut()
+        .zip(input.iter().positions(|&value| value))
+    {
+        *slot = index as i16;
+    }
+    sparse
+}
+
+/// The inverse of [`input_to_sparse`]. Negative (padding) indices are ignored, an index of
+/// [`INPUT_LENGTH`] or more panics.
+pub fn sparse_to_input(sparse: &[i16]) -> [bool; INPUT_LENGTH] {
+    let mut input = [false; INPUT_LENGTH];
+    for &index in sparse {
+        if let Ok(index) = usize::try_from(index) {
+            input[index] = true;
+        }
+    }
+    input
+}
+
+/// Encodes a position as [`PLANES`] of 64 squares each, indexed like [`Square`] (a1, b1, ..., h8).
+///
+/// Unlike [`chess_to_input`], this includes castling rights and the en passant square.
+pub fn chess_to_planes(chess: &impl Position) -> [[bool; 64]; PLANES.len()] {
+    let mut planes = [[false; 64]; PLANES.len()];
+
+    planes[..2 * 6].copy_from_slice(&board_to_planes(chess.board()));
+
+    planes[12] = [chess.turn().is_white(); 64];
+
+    let castles = chess.castles();
+    for (index, (color, side)) in Color::ALL
+        .into_iter()
+        .cartesian_product([CastlingSide::KingSide, CastlingSide::QueenSide])
+        .enumerate()
+    {
+        planes[13 + index] = [castles.has(color, side); 64];
+    }
+
+    if let Some(square) = chess.ep_square(EnPassantMode::Legal) {
+        planes[17][square as usize] = true;
+    }
+
+    planes
+}
+
+/// Encodes the pieces of a board as the first twelve [`PLANES`].
+pub fn board_to_planes(board: &Board) -> [[bool; 64]; 2 * 6] {
+    let mut planes = [[false; 64]; 2 * 6];
+    for (square, Piece { color, role }) in board.clone() {
+        let plane = match color {
+            Color::White => 0,
+            Color::Black => 6,
+        } + u32::from(role) as usize
+            - 1;
+        planes[plane][square as usize] = true;
+    }
+    planes
+}
+
+/// Converts [`PLANES`] back to the flat encoding of [`chess_to_input`].
+pub fn planes_to_input(planes: &[[bool; 64]]) -> [bool; INPUT_LENGTH] {
+    let mut input = [false; INPUT_LENGTH];
+    input[0] = planes[12][0];
+    for square in 0..64 {
+        let block = square * (1 + 2 * 6) + 1;
+        let mut empty = true;
+        for (plane, values) in planes[..2 * 6].iter().enumerate() {
+            if values[square] {
+                input[block + 1 + plane] = true;
+                empty = false;
+            }
+        }
+        input[block] = empty;
+    }
+    input
+}
+
+/// Reconstructs the board encoded by [`chess_to_input`].
+///
+/// Squares without any set value are left empty.
+pub fn input_to_board(input: &[bool]) -> Board {
+    let mut board = Board::empty();
+    for (index, square) in Square::ALL.into_iter().enumerate() {
+        let block = &input[index * (1 + 2 * 6) + 1..(index + 1) * (1 + 2 * 6) + 1];
+        let Some(offset) = block.iter().position(|&value| value) else {
+            continue;
+        };
+        if offset == 0 {
+            continue;
+        }
+        let (color, role) = if offset <= 6 {
+            (Color::White, offset)
+        } else {
+            (Color::Black, offset - 6)
+        };
+        let role = Role::try_from(role as u32).expect("offset is in 1..=6");
+        board.set_piece_at(square, Piece { color, role });
+    }
+    board
+}
+
+/// The inverse of [`chess_to_input`].
+///
+/// Castling rights and en passant squares are not part of the encoding, so castling rights
+/// are assumed wherever king and rook still stand on their initial squares.
+pub fn input_to_chess(input: &[bool]) -> Result<Chess, Box<PositionError<Chess>>> {
+    let board = input_to_board(input);
+
+    let mut castling_rights = Bitboard::EMPTY;
+    for color in Color::ALL {
+        let back_rank = color.relative_rank(Rank::First);
+        if board.piece_at(Square::from_coords(ChessFile::E, back_rank)) != Some(color.king()) {
+            continue;
+        }
+        for file in [ChessFile::A, ChessFile::H] {
+            let square = Square::from_coords(file, back_rank);
+            if board.piece_at(square) == Some(color.rook()) {
+                castling_rights.add(square);
+            }
+        }
+    }
+
+    let setup = Setup {
+        board,
+        turn: Color::from_white(input[0]),
+        castling_rights,
+        ..Setup::empty()
+    };
+    Chess::from_setup(setup, CastlingMode::Standard).map_err(Box::new)
+}
+
+/// The index of a move, `from * 64 + to`, with castling moves going to the king's destination.
+///
+/// Drops are indexed from [`MOVE_OUTPUTS`] on, by their role and target square.
 pub fn move_to_output(m: &Move) -> u16 {
-    let (from, to) = match m {
-        Move::Normal { from, to, .. } | Move::EnPassant { from, to, .. } => (*from, *to),
-        Move::Castle { king, rook } => {
-            let (king, king_to) = if king > rook {
-                (
-                    king,
-                    Square::from_coords(king.file().offset(-2).expect("Invalid rank"), king.rank()),
-                )
-            } else {
-                (
-                    king,
-                    Square::from_coords(king.file().offset(2).expect("Invalid rank"), king.rank()),
-                )
-            };
-            (*king, king_to)
+    castling_move_to_output(m, Castling::KingDestination)
+}
+
+/// Like [`move_to_output`], with castling moves labelled according to `castling`.
+pub fn castling_move_to_output(m: &Move, castling: Castling) -> u16 {
+    let (from, to) = match *m {
+        Move::Normal { from, to, .. } | Move::EnPassant { from, to, .. } => (from, to),
+        Move::Castle { king, rook } => match castling {
+            Castling::KingDestination => {
+                let side = CastlingSide::from_king_side(king < rook);
+                (king, Square::from_coords(side.king_to_file(), king.rank()))
+            }
+            Castling::KingTakesRook => (king, rook),
+        },
+        Move::Put { role, to } => {
+            let role = DROP_ROLES
+                .iter()
+                .position(|&drop| drop == role)
+                .expect("kings can not be dropped");
+            return (MOVE_OUTPUTS + role * 64 + usize::from(to)) as u16;
         }
-        Move::Put { .. } => unreachable!(),
     };
     let from: u8 = from.into();
     let to: u8 = to.into();
     from as u16 * 64 + to as u16
 }
 
+/// The number of move indices of a variant: Crazyhouse adds the drops.
+pub fn move_output_count(variant: Variant) -> usize {
+    match variant {
+        Variant::Crazyhouse => DROP_MOVE_OUTPUTS,
+        _ => MOVE_OUTPUTS,
+    }
+}
+
+/// Sets the bit of the move index of every legal move, most significant bit first.
+///
+/// Promotions to different pieces share an index, so they share a bit. The mask has
+/// [`move_output_count`] bits.
+pub fn legal_move_mask(chess: &VariantPosition, castling: Castling) -> Vec<u8> {
+    let mut mask = vec![0; move_output_count(chess.variant().into()) / 8];
+    for m in chess.legal_moves() {
+        let output = castling_move_to_output(&m, castling) as usize;
+        mask[output / 8] |= 1 << (7 - output % 8);
+    }
+    mask
+}
+
+/// Mirrors a move index vertically, e.g. e7e5 becomes e2e4, to match [`mirror_chess`].
+///
+/// Mirroring twice gives back the original index, so this also decodes mirrored labels.
+pub fn mirror_output(output: u16) -> u16 {
+    match output.checked_sub(MOVE_OUTPUTS as u16) {
+        Some(drop) => MOVE_OUTPUTS as u16 + (drop ^ 56),
+        None => output ^ (56 * 64 + 56),
+    }
+}
+
+/// Flips the board vertically and swaps the colours, so that the side to move changes colour.
+///
+/// Pockets and remaining checks swap sides as well. Racing Kings and Horde positions are not
+/// symmetric between the colours, so they can not be mirrored and give `None`.
+pub fn mirror_chess(chess: &VariantPosition) -> Option<VariantPosition> {
+    let setup = chess.clone().into_setup(EnPassantMode::Legal);
+    let setup = Setup {
+        board: mirror_board(&setup.board),
+        promoted: setup.promoted.flip_vertical(),
+        pockets: setup.pockets.map(|pockets| pockets.into_flipped()),
+        turn: !setup.turn,
+        castling_rights: setup.castling_rights.flip_vertical(),
+        ep_square: setup.ep_square.map(Square::flip_vertical),
+        remaining_checks: setup.remaining_checks.map(|checks| checks.into_flipped()),
+        ..setup
+    };
+    VariantPosition::from_setup(chess.variant(), setup, chess.castles().mode()).ok()
+}
+
+/// Flips a board vertically and swaps the colours of its pieces.
+pub fn mirror_board(board: &Board) -> Board {
+    let mut mirrored = Board::empty();
+    for (square, Piece { color, role }) in board.clone() {
+        mirrored.set_piece_at(
+            square.flip_vertical(),
+            Piece {
+                color: !color,
+                role,
+            },
+        );
+    }
+    mirrored
+}
+
+/// Mirrors a move index horizontally (a↔h), to match [`flip_chess`]. This is its own inverse.
+pub fn flip_output(output: u16) -> u16 {
+    match output.checked_sub(MOVE_OUTPUTS as u16) {
+        Some(drop) => MOVE_OUTPUTS as u16 + (drop ^ 7),
+        None => output ^ (7 * 64 + 7),
+    }
+}
+
+/// Mirrors a position horizontally (a↔h).
+///
+/// Positions with castling rights are not symmetric, so they can not be flipped.
+pub fn flip_chess(chess: &VariantPosition) -> Option<VariantPosition> {
+    if chess.castles().any() {
+        return None;
+    }
+    let mut setup = chess.clone().into_setup(EnPassantMode::Legal);
+    setup.board.flip_horizontal();
+    setup.promoted = setup.promoted.flip_horizontal();
+    setup.ep_square = setup.ep_square.map(Square::flip_horizontal);
+    VariantPosition::from_setup(chess.variant(), setup, chess.castles().mode()).ok()
+}
+
+/// Converts a move index to a UCI move without a promotion, or to a drop, if the index is in
+/// range.
+pub fn output_to_uci(output: u16) -> Option<Uci> {
+    if let Some(drop) = output.checked_sub(MOVE_OUTPUTS as u16) {
+        return Some(Uci::Put {
+            role: *DROP_ROLES.get(usize::from(drop / 64))?,
+            to: Square::try_from(drop % 64).ok()?,
+        });
+    }
+    Some(Uci::Normal {
+        from: Square::try_from(output / 64).ok()?,
+        to: Square::try_from(output % 64).ok()?,
+        promotion: None,
+    })
+}
+
+/// The inverse of [`move_to_output`]: finds the legal move of `chess` with the given index.
+///
+/// As promotions are not part of the index, queen promotions are preferred.
+pub fn output_to_move(chess: &impl Position, output: u16) -> Option<Move> {
+    castling_output_to_move(chess, output, Castling::KingDestination)
+}
+
+/// The inverse of [`castling_move_to_output`].
+///
+/// In Chess960, a king destination index can also be a normal king move, which is preferred.
+pub fn castling_output_to_move(
+    chess: &impl Position,
+    output: u16,
+    castling: Castling,
+) -> Option<Move> {
+    chess
+        .legal_moves()
+        .into_iter()
+        .filter(|m| castling_move_to_output(m, castling) == output)
+        .max_by_key(|m| (!m.is_castle(), m.promotion() == Some(Role::Queen)))
+}
+
 pub fn eval_to_output(eval: f32) -> f32 {
     // Calculate the sigmoid of the evaluation
     1.0 / (1.0 + (-eval).exp())
 }
 
-pub fn save_boards(io_pairs: impl Iterator<Item = (Chess, Move)>) -> io::Result<()> {
-    save_boards_outputs(io_pairs.map(|(input, output)| (input, move_to_output(&output))))
+/// The inverse of [`eval_to_output`].
+pub fn output_to_eval(output: f32) -> f32 {
+    (output / (1.0 - output)).ln()
 }
 
-pub fn save_boards_outputs<T>(io_pairs: impl Iterator<Item = (Chess, T)>) -> io::Result<()>
-where
-    T: npyz::Serialize + npyz::AutoSerialize,
-    T: Debug,
-{
-    // if ARGS.get_flag("dry-run") {
-    //     let (count, last) = io_pairs.enumerate().last().expect("no input");
-    //     println!("{} games would be converted to .npy files", count);
-    //     println!("last is {:?}", last);
-    //     return Ok(());
-    // }
+/// Metadata of the game a position was taken from.
+#[derive(Debug, Clone, Default)]
+pub struct GameInfo {
+    pub event: Option<String>,
+    pub white_elo: Option<u16>,
+    pub black_elo: Option<u16>,
+    pub time_control: Option<String>,
+    pub result: Option<String>,
+}
 
-    let neural_dir_prefix = ARGS
-        .get_one::<String>("output")
-        .expect("No output directory specified");
-    let neural_input_dir = &Path::new("../npy_files").join(neural_dir_prefix.clone() + "_input");
-    let neural_output_dir = &Path::new("../npy_files").join(neural_dir_prefix.clone() + "_output");
+impl GameInfo {
+    /// Records the PGN headers that are part of the game metadata.
+    pub fn header(&mut self, key: &[u8], value: &str) {
+        if value == "-" || value == "?" {
+            return;
+        }
+        match key {
+            b"Event" => self.event = Some(value.to_owned()),
+            b"WhiteElo" => self.white_elo = value.parse().ok(),
+            b"BlackElo" => self.black_elo = value.parse().ok(),
+            b"TimeControl" => self.time_control = Some(value.to_owned()),
+            b"Result" => self.result = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+}
 
-    let total_data = *ARGS
-        .get_one::<usize>("total")
-        .expect("No total data specified");
-    let boards_per_file = *ARGS
-        .get_one::<usize>("boards_per_file")
-        .expect("No boards per file specified");
-    let amount_of_files = total_data / boards_per_file;
+/// A position together with its label and, if known, the game it was taken from.
+#[derive(Debug, Clone)]
+pub struct Sample<T> {
+    pub chess: VariantPosition,
+    pub label: T,
+    pub game: Option<Arc<GameInfo>>,
+    /// The soft policy target, see [`policy::aggregate`](crate::policy::aggregate).
+    pub policy: Option<Policy>,
+    /// The earlier positions and last move, if the converter tracks them.
+    pub history: Option<History>,
+}
 
-    assert!(total_data % boards_per_file == 0);
+impl<T> Sample<T> {
+    pub fn new(chess: impl Into<VariantPosition>, label: T) -> Self {
+        Self {
+            chess: chess.into(),
+            label,
+            game: None,
+            policy: None,
+            history: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::fen::Fen;
 
-    if neural_input_dir.try_exists()? || neural_output_dir.try_exists()? {
-        panic!("Directory already exists!");
+    use super::*;
+
+    fn position(fen: &str) -> VariantPosition {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let chess: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        VariantPosition::from(chess)
     }
 
-    for dir in [neural_input_dir, neural_output_dir] {
-        fs::create_dir(dir)?;
+    #[test]
+    fn unpack_input_inverts_pack_input() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+        ] {
+            let input = chess_to_input(&position(fen));
+            assert_eq!(unpack_input(&pack_input(&input)), input);
+        }
     }
 
-    let io_pairs_chunked = io_pairs
-        .map(|(chess, output)| (chess_to_input(&chess), output))
-        .unique_by(|(input, _)| {
-            let mut hasher = DefaultHasher::new();
-            input.hash(&mut hasher);
-            hasher.finish()
-        })
-        .chunks(boards_per_file);
+    #[test]
+    fn sparse_to_input_inverts_input_to_sparse() {
+        // White to move sets all values, Black to move leaves the side to move as padding.
+        for (fen, padding) in [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0,
+            ),
+            ("8/8/8/4k3/8/8/8/4K3 b - - 0 1", 1),
+        ] {
+            let input = chess_to_input(&position(fen));
+            let sparse = input_to_sparse(&input);
+            assert_eq!(sparse.iter().filter(|&&index| index == -1).count(), padding);
+            assert!(sparse
+                .windows(2)
+                .all(|pair| pair[0] < pair[1] || pair[1] == -1));
+            assert_eq!(sparse_to_input(&sparse), input);
+        }
+    }
 
-    let start_time = Instant::now();
+    #[test]
+    fn planes_to_input_inverts_chess_to_planes() {
+        // Castling rights on one side each and an en passant square.
+        let chess = position("r3k2r/pppp1ppp/8/8/3Pp3/8/PPP1PPPP/R3K2R b Kq d3 0 1");
+        let planes = chess_to_planes(&chess);
+        assert_eq!(planes_to_input(&planes), chess_to_input(&chess));
 
-    for (chunk_index, chunk) in io_pairs_chunked
-        .into_iter()
-        .take(amount_of_files)
-        .enumerate()
-    {
-        let mut inputs = File::create(neural_input_dir.join(format!("{chunk_index}.npy")))?;
-        let mut input_writer = {
-            npyz::WriteOptions::new()
-                .default_dtype()
-                .shape(&[boards_per_file as u64, INPUT_LENGTH as u64])
-                .writer(&mut inputs)
-                .begin_nd()?
-        };
+        assert!(planes[5][Square::E1 as usize]);
+        assert!(planes[12].iter().all(|&white| !white));
+        let castling = planes[13..17]
+            .iter()
+            .map(|plane| plane[0])
+            .collect::<Vec<_>>();
+        assert_eq!(castling, [true, false, false, true]);
+        assert_eq!(
+            planes[17]
+                .iter()
+                .positions(|&value| value)
+                .collect::<Vec<_>>(),
+            [Square::D3 as usize]
+        );
+    }
 
-        let mut outputs = File::create(neural_output_dir.join(format!("{chunk_index}.npy")))?;
-        let mut output_writer = {
-            npyz::WriteOptions::new()
-                .default_dtype()
-                .shape(&[boards_per_file as u64])
-                .writer(&mut outputs)
-                .begin_nd()?
+    #[test]
+    fn legal_move_mask_sets_one_bit_per_move_index() {
+        let is_set = |mask: &[u8], from: Square, to: Square| {
+            let output = from as usize * 64 + to as usize;
+            mask[output / 8] & (1 << (7 - output % 8)) != 0
         };
+        let bits = |mask: &[u8]| mask.iter().map(|byte| byte.count_ones()).sum::<u32>();
 
-        chunk
-            .enumerate()
-            .for_each(|(board_index, (input, output))| {
-                // i is the index in the chunk, not the total index
-                // so we need to add the chunk index to it
-                debug(
-                    start_time,
-                    chunk_index * boards_per_file + board_index,
-                    total_data,
-                );
-                input_writer.extend(input).expect("IO error");
-                output_writer.push(&output).expect("IO error");
-            });
-
-        input_writer.finish()?;
-        output_writer.finish()?;
-    }
-
-    Ok(())
-}
-
-fn debug(start_time: Instant, count: usize, total_data: usize) {
-    if count % 1024 == 0 && count != 0 {
-        let amount_of_boards = total_data;
-        let remaining = amount_of_boards - count;
-        let elapsed = start_time.elapsed();
-        let time_per_board = elapsed / count as u32;
-
-        let raw_eta = remaining as u32 * time_per_board;
-        // Format the eta as a HH:MM:SS string
-        let eta = format!(
-            "{}:{:02}:{:02}",
-            raw_eta.as_secs() / 3600,
-            (raw_eta.as_secs() % 3600) / 60,
-            raw_eta.as_secs() % 60
-        );
-        eprint!(
-            "{count} / {board_amount} ({:.3}%) - {board_time:.4}ms per board - ETA: {eta}\r",
-            count as f32 * 100. / amount_of_boards as f32,
-            board_time = time_per_board.as_secs_f32() * 1000.0,
-            board_amount = total_data,
+        let start = position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let mask = legal_move_mask(&start, Castling::KingDestination);
+        assert_eq!(mask.len(), MOVE_OUTPUTS / 8);
+        assert_eq!(bits(&mask), 20);
+        assert!(is_set(&mask, Square::E2, Square::E4));
+        assert!(!is_set(&mask, Square::E2, Square::E5));
+
+        // Castling is labelled by the king's destination or by the rook.
+        let castling = position("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let mask = legal_move_mask(&castling, Castling::KingDestination);
+        assert!(is_set(&mask, Square::E1, Square::G1) && is_set(&mask, Square::E1, Square::C1));
+        assert!(!is_set(&mask, Square::E1, Square::H1));
+        let mask = legal_move_mask(&castling, Castling::KingTakesRook);
+        assert!(is_set(&mask, Square::E1, Square::H1) && is_set(&mask, Square::E1, Square::A1));
+        assert!(!is_set(&mask, Square::E1, Square::G1));
+
+        // Four promotions on c8 and four capturing on b8 share two bits, next to the king moves.
+        let promotion = position("1r2k3/2P5/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(promotion.legal_moves().len(), 8 + 5);
+        assert_eq!(
+            bits(&legal_move_mask(&promotion, Castling::KingDestination)),
+            2 + 5
         );
-        io::stderr().flush().expect("Couldn't flush stdout");
+    }
+
+    #[test]
+    fn chess960_castling_labels() {
+        // The king on b1 castles queenside to c1, which is also a normal king move.
+        let fen: Fen = "r5kr/8/8/8/8/8/8/RK5R w HAha - 0 1"
+            .parse()
+            .expect("valid FEN");
+        let chess: Chess = fen
+            .into_position(CastlingMode::Chess960)
+            .expect("legal position");
+        let castles = chess
+            .legal_moves()
+            .into_iter()
+            .filter(Move::is_castle)
+            .collect::<Vec<_>>();
+        assert_eq!(castles.len(), 2);
+        let label = |m: &Move, castling| output_to_uci(castling_move_to_output(m, castling));
+
+        let labels = |castling| {
+            castles
+                .iter()
+                .map(|m| label(m, castling).expect("move index").to_string())
+                .sorted()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(Castling::KingDestination), ["b1c1", "b1g1"]);
+        assert_eq!(labels(Castling::KingTakesRook), ["b1a1", "b1h1"]);
+
+        // Decoding prefers the normal king move where the labels coincide.
+        let b1c1 = Square::B1 as u16 * 64 + Square::C1 as u16;
+        let decoded = castling_output_to_move(&chess, b1c1, Castling::KingDestination);
+        assert!(decoded.is_some_and(|m| !m.is_castle()));
+        let b1g1 = Square::B1 as u16 * 64 + Square::G1 as u16;
+        let decoded = castling_output_to_move(&chess, b1g1, Castling::KingDestination);
+        assert!(decoded.is_some_and(|m| m.is_castle()));
+        for rook in [Square::A1, Square::H1] {
+            let output = Square::B1 as u16 * 64 + rook as u16;
+            let decoded = castling_output_to_move(&chess, output, Castling::KingTakesRook);
+            assert_eq!(
+                decoded,
+                Some(Move::Castle {
+                    king: Square::B1,
+                    rook
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn mirror_output_is_its_own_inverse() {
+        for output in 0..DROP_MOVE_OUTPUTS as u16 {
+            assert_eq!(mirror_output(mirror_output(output)), output);
+        }
+    }
+
+    #[test]
+    fn flip_output_matches_flipped_moves() {
+        // En passant and promotions, without castling rights.
+        let chess = position("8/4P1k1/8/3pP3/8/8/8/K7 w - d6 0 2");
+        let flipped = flip_chess(&chess).expect("no castling rights");
+        for m in chess.legal_moves() {
+            let Uci::Normal {
+                from,
+                to,
+                promotion,
+            } = m.to_uci(CastlingMode::Standard)
+            else {
+                panic!("no drops in chess");
+            };
+            let flipped_move = Uci::Normal {
+                from: from.flip_horizontal(),
+                to: to.flip_horizontal(),
+                promotion,
+            }
+            .to_move(&flipped)
+            .expect("flipped move is legal");
+            assert_eq!(
+                flip_output(move_to_output(&m)),
+                move_to_output(&flipped_move)
+            );
+        }
     }
 }
@@ -4,7 +4,9 @@ use clap::ArgMatches;
 use csv::{ReaderBuilder, StringRecord};
 use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Move, Position};
 
-use crate::save_boards;
+use neural_chess::Sample;
+
+use crate::dataset_writer;
 
 const CSV_FILE: &str = "puzzles.csv";
 
@@ -24,7 +26,7 @@ pub fn main(_options: &ArgMatches) -> Result<(), Box<dyn Error>> {
 
     let io_pairs = puzzles_to_boards(puzzles);
 
-    save_boards(io_pairs)?;
+    dataset_writer()?.write_moves(io_pairs.map(|(chess, m)| Sample::new(chess, m)))?;
 
     Ok(())
 }
@@ -40,22 +42,19 @@ fn parse_record(record: StringRecord) -> Result<Puzzle, Box<dyn Error>> {
 }
 
 fn puzzles_to_boards(puzzles: impl Iterator<Item = Puzzle>) -> impl Iterator<Item = (Chess, Move)> {
-    puzzles
-        .map(|Puzzle { fen, moves }| {
-            let mut chess: Chess = fen
-                .into_position(CastlingMode::Standard)
-                .expect("Invalid FEN");
-
-            moves.into_iter().map(move |m| {
-                let Ok(m) = m.to_move(&chess) else {
-                    println!("Board: {:?}", chess.board());
-                    println!("Invalid move: {}", m);
-                    panic!("Invalid move");
-                };
-                let chess_before = chess.clone();
-                chess.play_unchecked(&m);
-                (chess_before, m)
-            })
+    puzzles.flat_map(|Puzzle { fen, moves }| {
+        let mode = CastlingMode::detect(fen.as_setup());
+        let mut chess: Chess = fen.into_position(mode).expect("Invalid FEN");
+
+        moves.into_iter().map(move |m| {
+            let Ok(m) = m.to_move(&chess) else {
+                println!("Board: {:?}", chess.board());
+                println!("Invalid move: {}", m);
+                panic!("Invalid move");
+            };
+            let chess_before = chess.clone();
+            chess.play_unchecked(&m);
+            (chess_before, m)
         })
-        .flatten()
+    })
 }
@@ -0,0 +1,432 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use fs_err::File;
+use npyz::{npz, NpyFile, NpyReader};
+use zip::ZipArchive;
+
+use crate::{
+    manifest::{Compression, Encoding, Format, Layout, Manifest, Packing},
+    planes_to_input, sparse_to_input, unpack_input, INPUT_LENGTH, PACKED_INPUT_LENGTH,
+};
+
+pub const NPY_FILES_DIR: &str = "../npy_files";
+
+/// Returns the input and output directory of the dataset with the given prefix in `dir`.
+pub fn dataset_dirs(dir: &Path, prefix: &str) -> (PathBuf, PathBuf) {
+    (
+        dir.join(prefix.to_owned() + "_input"),
+        dir.join(prefix.to_owned() + "_output"),
+    )
+}
+
+/// Returns the directory of the `.npz` archives of the dataset with the given prefix in `dir`.
+pub fn npz_dir(dir: &Path, prefix: &str) -> PathBuf {
+    dir.join(prefix.to_owned() + "_npz")
+}
+
+/// The file extension of the chunks of an npy, npz or TFRecord dataset.
+pub fn chunk_extension(manifest: &Manifest) -> &'static str {
+    match (manifest.format, manifest.compression) {
+        (Format::Npz, Compression::Zstd) => "npz.zst",
+        (Format::Npz, _) => "npz",
+        (Format::Tfrecord, Compression::Gzip) => "tfrecord.gz",
+        (Format::Tfrecord, _) => "tfrecord",
+        _ => "npy",
+    }
+}
+
+/// Counts the chunks `0.npy`, `1.npy`, ... in the input directory of a dataset in `dir`.
+pub fn chunk_count(dir: &Path, prefix: &str) -> io::Result<usize> {
+    let manifest = Manifest::read(dir, prefix)?;
+    let chunk_dir = match manifest.format {
+        Format::Npz => npz_dir(dir, prefix),
+        _ => dataset_dirs(dir, prefix).0,
+    };
+    let extension = chunk_extension(&manifest);
+    let mut count = 0;
+    while chunk_dir
+        .join(format!("{count}.{extension}"))
+        .try_exists()?
+    {
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Where the arrays of a chunk are read from: a file, or memory for compressed chunks.
+pub enum ArraySource {
+    File(BufReader<File>),
+    Memory(Cursor<Vec<u8>>),
+}
+
+impl Read for ArraySource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ArraySource::File(file) => file.read(buf),
+            ArraySource::Memory(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for ArraySource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ArraySource::File(file) => file.seek(pos),
+            ArraySource::Memory(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// Opens the input and output array of one chunk.
+pub fn open_chunk(
+    dir: &Path,
+    prefix: &str,
+    chunk_index: usize,
+    manifest: &Manifest,
+) -> io::Result<(NpyFile<ArraySource>, NpyFile<ArraySource>)> {
+    let mut arrays = open_arrays(dir, prefix, chunk_index, manifest, &["input", "output"])?;
+    let outputs = arrays.pop().expect("two arrays");
+    let inputs = arrays.pop().expect("two arrays");
+    Ok((inputs, outputs))
+}
+
+/// Opens the named arrays (e.g. `input`, `output` or `legal`) of one chunk.
+///
+/// Arrays in `.npz` archives are decompressed into memory, as zip entries cannot be seeked.
+pub fn open_arrays(
+    dir: &Path,
+    prefix: &str,
+    chunk_index: usize,
+    manifest: &Manifest,
+    names: &[&str],
+) -> io::Result<Vec<NpyFile<ArraySource>>> {
+    match manifest.format {
+        Format::Npy => names
+            .iter()
+            .map(|name| {
+                let path = dir
+                    .join(format!("{prefix}_{name}"))
+                    .join(format!("{chunk_index}.npy"));
+                NpyFile::new(ArraySource::File(BufReader::new(File::open(path)?)))
+            })
+            .collect(),
+        Format::Npz => {
+            let path =
+                npz_dir(dir, prefix).join(format!("{chunk_index}.{}", chunk_extension(manifest)));
+            let archive = match manifest.compression {
+                Compression::Zstd => zstd::decode_all(BufReader::new(File::open(path)?))?,
+                Compression::None | Compression::Deflate | Compression::Gzip => fs_err::read(path)?,
+            };
+            let mut archive = ZipArchive::new(Cursor::new(archive))?;
+            names
+                .iter()
+                .map(|name| {
+                    let mut entry = archive.by_name(&npz::file_name_from_array_name(name))?;
+                    let mut array = Vec::with_capacity(entry.size() as usize);
+                    entry.read_to_end(&mut array)?;
+                    NpyFile::new(ArraySource::Memory(Cursor::new(array)))
+                })
+                .collect()
+        }
+        Format::Tfrecord | Format::Parquet | Format::Arrow => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "only npy and npz datasets can be read, not {:?}",
+                manifest.format
+            ),
+        )),
+    }
+}
+
+/// A label as stored in the output files: either a move index or an evaluation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Label {
+    Move(u16),
+    Eval(f32),
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Label::Move(index) => write!(f, "{index}"),
+            Label::Eval(eval) => write!(f, "{eval}"),
+        }
+    }
+}
+
+impl From<u16> for Label {
+    fn from(index: u16) -> Self {
+        Label::Move(index)
+    }
+}
+
+impl From<f32> for Label {
+    fn from(eval: f32) -> Self {
+        Label::Eval(eval)
+    }
+}
+
+enum LabelReader {
+    Move(NpyReader<u16, ArraySource>),
+    Eval(NpyReader<f32, ArraySource>),
+}
+
+impl LabelReader {
+    fn read_at(&mut self, index: u64) -> io::Result<Label> {
+        Ok(match self {
+            LabelReader::Move(reader) => Label::Move(reader.read_at(index)?),
+            LabelReader::Eval(reader) => Label::Eval(reader.read_at(index)?),
+        })
+    }
+
+    fn total_len(&self) -> u64 {
+        match self {
+            LabelReader::Move(reader) => reader.total_len(),
+            LabelReader::Eval(reader) => reader.total_len(),
+        }
+    }
+}
+
+enum InputReader {
+    Dense(NpyReader<bool, ArraySource>, usize),
+    Packed(NpyReader<u8, ArraySource>, usize),
+    Sparse(NpyReader<i16, ArraySource>, usize),
+    Planes(NpyReader<bool, ArraySource>, Layout, usize),
+}
+
+impl InputReader {
+    fn read_row(&mut self, row: u64) -> io::Result<[bool; INPUT_LENGTH]> {
+        match self {
+            InputReader::Dense(reader, columns) => {
+                let mut input = [false; INPUT_LENGTH];
+                reader.seek_to(row * *columns as u64)?;
+                for (value, read) in input.iter_mut().zip(reader) {
+                    *value = read?;
+                }
+                Ok(input)
+            }
+            InputReader::Packed(reader, columns) => {
+                let mut packed = [0; PACKED_INPUT_LENGTH];
+                reader.seek_to(row * *columns as u64)?;
+                for (value, read) in packed.iter_mut().zip(reader) {
+                    *value = read?;
+                }
+                Ok(unpack_input(&packed))
+            }
+            InputReader::Sparse(reader, width) => {
+                let mut sparse = vec![0; *width];
+                reader.seek_to(row * *width as u64)?;
+                for (value, read) in sparse.iter_mut().zip(reader) {
+                    *value = read?;
+                }
+                if let Some(index) = sparse.iter().find(|&&index| index >= INPUT_LENGTH as i16) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("sparse input index {index} out of range"),
+                    ));
+                }
+                Ok(sparse_to_input(&sparse))
+            }
+            InputReader::Planes(reader, layout, count) => {
+                let mut planes = vec![[false; 64]; *count];
+                reader.seek_to(row * *count as u64 * 64)?;
+                for (index, read) in (0..*count * 64).zip(reader) {
+                    let (plane, square) = match layout {
+                        Layout::PlanesLast => (index % *count, index / *count),
+                        _ => (index / 64, index % 64),
+                    };
+                    planes[plane][square] = read?;
+                }
+                Ok(planes_to_input(&planes))
+            }
+        }
+    }
+}
+
+/// Reads the rows of one chunk of a dataset, i.e. one input and one output file.
+pub struct ChunkReader {
+    inputs: InputReader,
+    outputs: LabelReader,
+    rows: u64,
+    next_row: u64,
+}
+
+impl ChunkReader {
+    /// Opens a chunk of the dataset with the given prefix in `dir`.
+    pub fn open(dir: &Path, prefix: &str, chunk_index: usize) -> io::Result<Self> {
+        let manifest = Manifest::read(dir, prefix)?;
+        let (inputs, outputs) = open_chunk(dir, prefix, chunk_index, &manifest)?;
+        Self::new(inputs, outputs, &manifest)
+    }
+
+    /// Reads from already opened arrays, as returned by [`open_chunk`].
+    pub fn new(
+        inputs: NpyFile<ArraySource>,
+        outputs: NpyFile<ArraySource>,
+        manifest: &Manifest,
+    ) -> io::Result<Self> {
+        if manifest.encoding != Encoding::Board {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{:?} inputs can not be decoded", manifest.encoding),
+            ));
+        }
+        if !manifest.has_valid_input_length() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported input length {}", manifest.input_length),
+            ));
+        }
+
+        if inputs.shape().len() < 2 || inputs.shape()[1..] != manifest.input_shape() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected input shape {:?}", inputs.shape()),
+            ));
+        }
+        let rows = inputs.shape()[0];
+        let inputs = match (manifest.layout, manifest.packing) {
+            (Layout::PlanesFirst | Layout::PlanesLast, _) => inputs.data().map(|reader| {
+                let count = manifest.planes.as_ref().map_or(0, Vec::len);
+                InputReader::Planes(reader, manifest.layout, count)
+            }),
+            (Layout::Flat, Packing::None) => inputs
+                .data()
+                .map(|reader| InputReader::Dense(reader, manifest.input_columns())),
+            (Layout::Flat, Packing::Packbits) => inputs
+                .data()
+                .map(|reader| InputReader::Packed(reader, manifest.input_columns())),
+            (Layout::Flat, Packing::Sparse) => inputs
+                .data()
+                .map(|reader| InputReader::Sparse(reader, manifest.input_columns())),
+        }
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let outputs = match outputs.try_data::<u16>() {
+            Ok(reader) => LabelReader::Move(reader),
+            Err(outputs) => LabelReader::Eval(
+                outputs
+                    .data::<f32>()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            ),
+        };
+        if outputs.total_len() != rows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "input has {rows} rows but output has {} rows",
+                    outputs.total_len()
+                ),
+            ));
+        }
+
+        Ok(Self {
+            inputs,
+            outputs,
+            rows,
+            next_row: 0,
+        })
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn read_row(&mut self, row: u64) -> io::Result<([bool; INPUT_LENGTH], Label)> {
+        if row >= self.rows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("row {row} is out of range (chunk has {} rows)", self.rows),
+            ));
+        }
+
+        // Skip the row even if it can not be read, so that iterating continues after corrupt rows.
+        self.next_row = row + 1;
+        let input = self.inputs.read_row(row)?;
+        let label = self.outputs.read_at(row)?;
+
+        Ok((input, label))
+    }
+}
+
+impl Iterator for ChunkReader {
+    type Item = io::Result<([bool; INPUT_LENGTH], Label)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        (self.next_row < self.rows).then(|| self.read_row(self.next_row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use shakmaty::{Chess, Position, Square};
+
+    use super::*;
+    use crate::{
+        chess_to_input, writer::InputConfig, DatasetConfig, DatasetWriter, Sample, PLANES,
+    };
+
+    #[test]
+    fn plane_layouts_read_back() {
+        let start = Chess::default();
+        let samples = start
+            .legal_moves()
+            .into_iter()
+            .take(2)
+            .map(|m| start.clone().play(&m).expect("legal"))
+            .collect::<Vec<_>>();
+
+        for layout in [Layout::PlanesFirst, Layout::PlanesLast] {
+            let dir =
+                env::temp_dir().join(format!("neural-chess-{layout:?}-{}", std::process::id()));
+            let _ = fs_err::remove_dir_all(&dir);
+            fs_err::create_dir_all(&dir).expect("temporary directory");
+            DatasetWriter::builder("planes")
+                .output_dir(&dir)
+                .config(DatasetConfig {
+                    total: 2,
+                    boards_per_file: 2,
+                    input: InputConfig {
+                        layout,
+                        ..InputConfig::default()
+                    },
+                    ..DatasetConfig::default()
+                })
+                .build()
+                .expect("valid config")
+                .write(
+                    samples
+                        .iter()
+                        .map(|chess| Sample::new(chess.clone(), 0.5_f32)),
+                )
+                .expect("dataset written");
+
+            let rows = ChunkReader::open(&dir, "planes", 0)
+                .expect("chunk")
+                .collect::<io::Result<Vec<_>>>()
+                .expect("rows");
+            let inputs = NpyFile::new(File::open(dir.join("planes_input/0.npy")).expect("inputs"))
+                .and_then(NpyFile::into_vec::<bool>)
+                .expect("bool inputs");
+            fs_err::remove_dir_all(&dir).expect("temporary directory");
+
+            for ((input, _), chess) in rows.iter().zip(&samples) {
+                assert_eq!(*input, chess_to_input(chess));
+            }
+            // The white king on e1 in the first row.
+            let (plane, square) = (5, Square::E1 as usize);
+            let index = match layout {
+                Layout::PlanesLast => square * PLANES.len() + plane,
+                _ => plane * 64 + square,
+            };
+            assert!(inputs[index]);
+            assert_eq!(inputs.len(), 2 * PLANES.len() * 64);
+        }
+    }
+}
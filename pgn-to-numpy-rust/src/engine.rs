@@ -0,0 +1,284 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use shakmaty::{
+    fen::Fen, uci::Uci, variant::VariantPosition, CastlingMode, Color, EnPassantMode, Position,
+};
+
+use crate::{
+    castling_move_to_output,
+    manifest::{Castling, Variant},
+    policy::{self, Policy, Score},
+    Sample,
+};
+
+/// How long an engine searches each position.
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    Depth(u32),
+    Nodes(u64),
+}
+
+/// One principal variation reported by an engine.
+#[derive(Debug, Clone)]
+pub struct Line {
+    /// The score from the point of view of the side to move.
+    pub score: Score,
+    pub moves: Vec<Uci>,
+}
+
+/// The result of a search: the last reported line of every multi-PV index, best first, and the
+/// best move.
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub lines: Vec<Line>,
+    /// `None` if the position has no legal moves, which engines report as `bestmove (none)`.
+    pub best_move: Option<Uci>,
+}
+
+impl Analysis {
+    /// The score of the best line in pawns from White's point of view. Forced mates are
+    /// infinite, like the `[%eval #3]` comments read by
+    /// [`EvalVisitor`](crate::visitor::EvalVisitor).
+    pub fn eval(&self, turn: Color) -> Option<f32> {
+        let eval = match self.lines.first()?.score {
+            Score::Centipawns(centipawns) => centipawns as f32 / 100.0,
+            Score::Mate(moves) if moves > 0 => f32::INFINITY,
+            Score::Mate(_) => f32::NEG_INFINITY,
+        };
+        Some(match turn {
+            Color::White => eval,
+            Color::Black => -eval,
+        })
+    }
+
+    /// The soft policy target of the first moves of all lines, see [`policy::softmax`]. Fails if
+    /// a line starts with an illegal move.
+    pub fn policy(
+        &self,
+        chess: &VariantPosition,
+        castling: Castling,
+        temperature: f32,
+    ) -> Option<Policy> {
+        let scores = self
+            .lines
+            .iter()
+            .map(|line| {
+                let m = line.moves.first()?.to_move(chess).ok()?;
+                Some((castling_move_to_output(&m, castling), line.score))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(policy::softmax(&scores, temperature))
+    }
+}
+
+/// An engine process that speaks the Universal Chess Interface.
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    chess960: bool,
+}
+
+impl UciEngine {
+    /// Starts the engine and waits until it is ready. Reports `multi_pv` lines per search and,
+    /// for variants other than chess, sets the `UCI_Variant` option.
+    pub fn spawn(path: &Path, multi_pv: usize, variant: Variant) -> io::Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        let mut engine = Self {
+            stdin: child.stdin.take().expect("piped stdin"),
+            stdout: BufReader::new(child.stdout.take().expect("piped stdout")),
+            child,
+            chess960: false,
+        };
+
+        engine.send("uci")?;
+        engine.read_until("uciok")?;
+        if multi_pv > 1 {
+            engine.send(&format!("setoption name MultiPV value {multi_pv}"))?;
+        }
+        if variant != Variant::Chess {
+            let name = shakmaty::variant::Variant::from(variant).uci();
+            engine.send(&format!("setoption name UCI_Variant value {name}"))?;
+        }
+        engine.send("isready")?;
+        engine.read_until("readyok")?;
+        Ok(engine)
+    }
+
+    /// Searches a position and collects the lines the engine reports until its best move.
+    pub fn analyse(&mut self, chess: &VariantPosition, limit: Limit) -> io::Result<Analysis> {
+        let chess960 = chess.castles().mode() == CastlingMode::Chess960;
+        if chess960 != self.chess960 {
+            self.send(&format!("setoption name UCI_Chess960 value {chess960}"))?;
+            self.chess960 = chess960;
+        }
+        let fen = Fen::from_position(chess.clone(), EnPassantMode::Legal);
+        self.send(&format!("position fen {fen}"))?;
+        self.send(&match limit {
+            Limit::Depth(depth) => format!("go depth {depth}"),
+            Limit::Nodes(nodes) => format!("go nodes {nodes}"),
+        })?;
+
+        let mut lines: Vec<Option<Line>> = Vec::new();
+        loop {
+            let response = self.read_line()?;
+            let mut tokens = response.split_whitespace();
+            match tokens.next() {
+                Some("info") => {
+                    if let Some((index, line)) = parse_info(tokens) {
+                        if lines.len() < index {
+                            lines.resize(index, None);
+                        }
+                        lines[index - 1] = Some(line);
+                    }
+                }
+                Some("bestmove") => {
+                    let best_move = match tokens.next() {
+                        Some("(none)") => None,
+                        Some(m) => Some(m.parse().map_err(|_| invalid_response(&response))?),
+                        None => return Err(invalid_response(&response)),
+                    };
+                    return Ok(Analysis {
+                        lines: lines.into_iter().flatten().collect(),
+                        best_move,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the engine exited",
+            ));
+        }
+        Ok(line)
+    }
+
+    fn read_until(&mut self, response: &str) -> io::Result<()> {
+        while self.read_line()?.trim() != response {}
+        Ok(())
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.wait();
+    }
+}
+
+fn invalid_response(response: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid engine response {:?}", response.trim()),
+    )
+}
+
+/// Reads the multi-PV index (starting at 1), score and moves of an `info` line. Lines without a
+/// principal variation and bounds of an unfinished search are ignored.
+fn parse_info<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Option<(usize, Line)> {
+    let mut index = 1;
+    let mut score = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "multipv" => index = tokens.next()?.parse().ok().filter(|&index| index > 0)?,
+            "score" => {
+                score = Some(match tokens.next()? {
+                    "cp" => Score::Centipawns(tokens.next()?.parse().ok()?),
+                    "mate" => Score::Mate(tokens.next()?.parse().ok()?),
+                    _ => return None,
+                })
+            }
+            "lowerbound" | "upperbound" => return None,
+            "pv" => {
+                let moves = tokens.map(str::parse).collect::<Result<_, _>>().ok()?;
+                return Some((
+                    index,
+                    Line {
+                        score: score?,
+                        moves,
+                    },
+                ));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Engine processes that search positions in parallel, one thread each.
+pub struct EnginePool {
+    engines: Vec<UciEngine>,
+}
+
+impl EnginePool {
+    /// Starts `count` engines, see [`UciEngine::spawn`].
+    pub fn spawn(path: &Path, count: usize, multi_pv: usize, variant: Variant) -> io::Result<Self> {
+        let engines = (0..count.max(1))
+            .map(|_| UciEngine::spawn(path, multi_pv, variant))
+            .collect::<io::Result<_>>()?;
+        Ok(Self { engines })
+    }
+
+    /// Searches the positions of the samples. The results arrive in the order the searches
+    /// finish. An engine stops searching after its first error.
+    ///
+    /// The searches stop once the returned iterator is dropped.
+    pub fn analyse<T: Send + 'static>(
+        self,
+        samples: impl Iterator<Item = Sample<T>> + Send + 'static,
+        limit: Limit,
+    ) -> impl Iterator<Item = io::Result<(Sample<T>, Analysis)>> {
+        let capacity = 2 * self.engines.len();
+        let (job_sender, jobs) = mpsc::sync_channel::<Sample<T>>(capacity);
+        let (result_sender, results) = mpsc::sync_channel(capacity);
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        thread::spawn(move || {
+            for sample in samples {
+                if job_sender.send(sample).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for mut engine in self.engines {
+            let jobs = Arc::clone(&jobs);
+            let result_sender = result_sender.clone();
+            thread::spawn(move || loop {
+                let Ok(sample) = jobs.lock().expect("job queue").recv() else {
+                    break;
+                };
+                let result = engine
+                    .analyse(&sample.chess, limit)
+                    .map(|analysis| (sample, analysis));
+                let failed = result.is_err();
+                if result_sender.send(result).is_err() || failed {
+                    break;
+                }
+            });
+        }
+
+        results.into_iter()
+    }
+}
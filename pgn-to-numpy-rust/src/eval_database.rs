@@ -0,0 +1,213 @@
+use serde::Deserialize;
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Color, Move, Position};
+
+use crate::{
+    castling_move_to_output,
+    manifest::Castling,
+    policy::{self, Policy, Score},
+};
+
+/// One line of the Lichess evaluation database (`lichess_db_eval.jsonl`): a position with its
+/// cloud evaluations at different depths.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EvalRecord {
+    /// The position, without the halfmove clock and move number.
+    pub fen: String,
+    pub evals: Vec<Evaluation>,
+}
+
+/// One engine analysis of a position.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Evaluation {
+    /// The principal variations, best first.
+    pub pvs: Vec<PrincipalVariation>,
+    pub knodes: u64,
+    pub depth: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrincipalVariation {
+    /// Centipawns from White's point of view, unless the line is a forced mate.
+    pub cp: Option<i32>,
+    /// Moves until mate, negative if Black mates.
+    pub mate: Option<i32>,
+    /// The moves of the variation in UCI notation, separated by spaces.
+    pub line: String,
+}
+
+impl EvalRecord {
+    /// The position of the record, if its FEN is valid.
+    pub fn position(&self) -> Option<Chess> {
+        let fen: Fen = self.fen.parse().ok()?;
+        let mode = CastlingMode::detect(fen.as_setup());
+        fen.into_position(mode).ok()
+    }
+
+    /// The evaluation with the greatest depth, and of those the one that searched the most nodes.
+    pub fn deepest(&self) -> Option<&Evaluation> {
+        self.evals
+            .iter()
+            .max_by_key(|evaluation| (evaluation.depth, evaluation.knodes))
+    }
+}
+
+impl Evaluation {
+    /// The soft policy target of the first `top_k` variations, see [`policy::softmax`]. Fails
+    /// if a variation has no score or an illegal first move.
+    pub fn policy(
+        &self,
+        chess: &Chess,
+        castling: Castling,
+        temperature: f32,
+        top_k: usize,
+    ) -> Option<Policy> {
+        let scores = self
+            .pvs
+            .iter()
+            .take(top_k)
+            .map(|pv| {
+                let m = pv.first_move(chess)?;
+                Some((
+                    castling_move_to_output(&m, castling),
+                    pv.score(chess.turn())?,
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(policy::softmax(&scores, temperature))
+    }
+}
+
+impl PrincipalVariation {
+    /// The evaluation in pawns from White's point of view. Forced mates are infinite, like the
+    /// `[%eval #3]` comments read by [`EvalVisitor`](crate::visitor::EvalVisitor).
+    pub fn eval(&self) -> Option<f32> {
+        match (self.cp, self.mate) {
+            (Some(cp), _) => Some(cp as f32 / 100.0),
+            (None, Some(mate)) if mate > 0 => Some(f32::INFINITY),
+            (None, Some(_)) => Some(f32::NEG_INFINITY),
+            (None, None) => None,
+        }
+    }
+
+    /// The score of the variation from the point of view of `turn`.
+    pub fn score(&self, turn: Color) -> Option<Score> {
+        let sign = match turn {
+            Color::White => 1,
+            Color::Black => -1,
+        };
+        match (self.cp, self.mate) {
+            (Some(cp), _) => Some(Score::Centipawns(sign * cp)),
+            (None, Some(mate)) => Some(Score::Mate(sign * mate)),
+            (None, None) => None,
+        }
+    }
+
+    /// The first move of the variation, if it is legal in `chess`.
+    pub fn first_move(&self, chess: &Chess) -> Option<Move> {
+        let uci: Uci = self.line.split_whitespace().next()?.parse().ok()?;
+        uci.to_move(chess).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::Square;
+
+    use super::*;
+
+    /// A record of the format of `lichess_db_eval.jsonl`, with Black to move.
+    const RECORD: &str = r#"{"fen":"rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq -","evals":[{"pvs":[{"cp":-35,"line":"d7d5 e2e3"}],"knodes":900,"depth":30},{"pvs":[{"mate":-1,"line":"d8h4"},{"cp":-250,"line":"b8c6 e2e3"}],"knodes":200,"depth":40},{"pvs":[{"mate":-1,"line":"d8h4"}],"knodes":100,"depth":40}]}"#;
+
+    #[test]
+    fn records_parse_and_pick_the_deepest_evaluation() {
+        let record: EvalRecord = serde_json::from_str(RECORD).expect("valid record");
+        let chess = record.position().expect("valid FEN without move counters");
+        assert_eq!(chess.turn(), Color::Black);
+
+        // Of the two evaluations of depth 40, the one that searched more nodes.
+        let deepest = record.deepest().expect("evaluations");
+        assert_eq!((deepest.depth, deepest.knodes), (40, 200));
+        assert_eq!(deepest.pvs.len(), 2);
+        let queen_mate = deepest.pvs[0].first_move(&chess).expect("legal move");
+        assert_eq!(queen_mate.to().to_string(), "h4");
+
+        let record = EvalRecord {
+            evals: Vec::new(),
+            ..record
+        };
+        assert!(record.deepest().is_none());
+    }
+
+    #[test]
+    fn mates_are_signed_from_whites_point_of_view() {
+        let pv = |cp, mate| PrincipalVariation {
+            cp,
+            mate,
+            line: String::new(),
+        };
+        assert_eq!(pv(Some(-35), None).eval(), Some(-0.35));
+        assert_eq!(pv(None, Some(3)).eval(), Some(f32::INFINITY));
+        assert_eq!(pv(None, Some(-1)).eval(), Some(f32::NEG_INFINITY));
+        assert_eq!(pv(None, None).eval(), None);
+
+        // Scores are from the side to move.
+        assert_eq!(
+            pv(Some(-35), None).score(Color::Black),
+            Some(Score::Centipawns(35))
+        );
+        assert_eq!(pv(None, Some(-1)).score(Color::Black), Some(Score::Mate(1)));
+        assert_eq!(
+            pv(None, Some(-1)).score(Color::White),
+            Some(Score::Mate(-1))
+        );
+        assert_eq!(pv(None, None).score(Color::White), None);
+    }
+
+    #[test]
+    fn illegal_first_moves() {
+        let chess = Chess::default();
+        let pv = |line: &str| PrincipalVariation {
+            cp: Some(0),
+            mate: None,
+            line: line.to_owned(),
+        };
+        assert!(pv("e2e4 e7e5").first_move(&chess).is_some());
+        assert!(pv("e2e5").first_move(&chess).is_none());
+        assert!(pv("").first_move(&chess).is_none());
+    }
+
+    #[test]
+    fn policy_of_the_lines() {
+        let record: EvalRecord = serde_json::from_str(RECORD).expect("valid record");
+        let chess = record.position().expect("valid FEN");
+        let deepest = record.deepest().expect("evaluations");
+        let queen_mate = Square::D8 as u16 * 64 + Square::H4 as u16;
+        let knight = Square::B8 as u16 * 64 + Square::C6 as u16;
+
+        // The mate takes all the probability, unless only the first line is taken.
+        let policy = deepest
+            .policy(&chess, Castling::KingDestination, 1.0, 2)
+            .expect("scored legal lines");
+        assert_eq!(
+            policy.iter().map(|&(index, _)| index).collect::<Vec<_>>(),
+            [queen_mate, knight]
+        );
+        assert!(policy[1].1 < 1e-6);
+        let policy = deepest.policy(&chess, Castling::KingDestination, 1.0, 1);
+        assert_eq!(policy, Some(vec![(queen_mate, 1.0)]));
+
+        // Lines without a score or with an illegal first move give no policy.
+        let mut unscored = deepest.clone();
+        unscored.pvs[1].cp = None;
+        assert_eq!(
+            unscored.policy(&chess, Castling::KingDestination, 1.0, 2),
+            None
+        );
+        let mut illegal = deepest.clone();
+        illegal.pvs[1].line = "e2e4".to_owned();
+        assert_eq!(
+            illegal.policy(&chess, Castling::KingDestination, 1.0, 2),
+            None
+        );
+    }
+}
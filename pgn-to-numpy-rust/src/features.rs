@@ -0,0 +1,153 @@
+use shakmaty::{attacks, Bitboard, Board, ByColor, Color, Position};
+
+/// The tactical feature planes written by [`chess_to_features`], in order.
+pub const FEATURE_PLANES: [&str; 13] = [
+    "white-attacks",
+    "black-attacks",
+    "white-pinned",
+    "black-pinned",
+    "checkers",
+    "white-hanging",
+    "black-hanging",
+    "white-king-zone-attacked",
+    "black-king-zone-attacked",
+    "mobility-1",
+    "mobility-2",
+    "mobility-4",
+    "mobility-8",
+];
+
+/// The mobility planes mark pieces that can move to at least this many squares.
+const MOBILITY_THRESHOLDS: [usize; 4] = [1, 2, 4, 8];
+
+/// Computes handcrafted tactical features of a position as [`FEATURE_PLANES`]:
+///
+/// - the squares attacked by each side,
+/// - pieces pinned to their own king,
+/// - pieces giving check to the side to move,
+/// - pieces (except kings) that are attacked but not defended,
+/// - the squares around each king that the opponent attacks,
+/// - and the mobility of every piece, i.e. the number of attacked squares not occupied by its
+///   own side, as thermometer planes.
+pub fn chess_to_features(chess: &impl Position) -> [[bool; 64]; FEATURE_PLANES.len()] {
+    let board = chess.board();
+    let mut planes = [Bitboard::EMPTY; FEATURE_PLANES.len()];
+
+    let attacked = ByColor::new_with(|color| attacked_squares(board, color));
+    for (index, color) in Color::ALL.into_iter().enumerate() {
+        let ours = board.by_color(color);
+        let theirs = *attacked.get(!color);
+        planes[index] = *attacked.get(color);
+        planes[2 + index] = pinned(board, color);
+        planes[5 + index] = ours & !board.kings() & theirs & !*attacked.get(color);
+        if let Some(king) = board.king_of(color) {
+            planes[7 + index] = (attacks::king_attacks(king) | king) & theirs;
+        }
+    }
+    planes[4] = chess.checkers();
+
+    for square in board.occupied() {
+        let color = board.color_at(square).expect("occupied square");
+        let mobility = (board.attacks_from(square) & !board.by_color(color)).count();
+        for (index, threshold) in MOBILITY_THRESHOLDS.into_iter().enumerate() {
+            if mobility >= threshold {
+                planes[9 + index].add(square);
+            }
+        }
+    }
+
+    planes.map(|plane| {
+        let mut values = [false; 64];
+        for square in plane {
+            values[square as usize] = true;
+        }
+        values
+    })
+}
+
+/// All squares attacked by the pieces of one side.
+fn attacked_squares(board: &Board, color: Color) -> Bitboard {
+    board
+        .by_color(color)
+        .into_iter()
+        .fold(Bitboard::EMPTY, |attacked, square| {
+            attacked | board.attacks_from(square)
+        })
+}
+
+/// The pieces of one side that can not move off the line between their king and an enemy slider.
+fn pinned(board: &Board, color: Color) -> Bitboard {
+    let Some(king) = board.king_of(color) else {
+        return Bitboard::EMPTY;
+    };
+    let them = board.by_color(!color);
+    let snipers = (attacks::rook_attacks(king, Bitboard::EMPTY) & board.rooks_and_queens()
+        | attacks::bishop_attacks(king, Bitboard::EMPTY) & board.bishops_and_queens())
+        & them;
+
+    snipers.into_iter().fold(Bitboard::EMPTY, |pinned, sniper| {
+        let blockers = attacks::between(king, sniper) & board.occupied();
+        if blockers.count() == 1 && blockers.is_subset(board.by_color(color)) {
+            pinned | blockers
+        } else {
+            pinned
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::{fen::Fen, CastlingMode, Chess, Square};
+
+    use super::*;
+
+    /// The squares set in each feature plane of the position, by plane name.
+    fn features(fen: &str) -> impl Fn(&str) -> Vec<Square> {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let chess: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        let planes = chess_to_features(&chess);
+        move |name| {
+            let plane = FEATURE_PLANES.iter().position(|&other| other == name);
+            Square::ALL
+                .into_iter()
+                .filter(|&square| planes[plane.expect("feature plane")][square as usize])
+                .collect()
+        }
+    }
+
+    #[test]
+    fn pins_hanging_pieces_and_mobility() {
+        // The bishop pins the knight to the king, the rook attacks the undefended knight on h5.
+        let features = features("4k3/8/8/7n/1b6/8/3N4/4K2R w K - 0 1");
+        assert_eq!(features("white-pinned"), [Square::D2]);
+        assert_eq!(features("black-pinned"), []);
+        assert_eq!(features("checkers"), []);
+        assert_eq!(features("white-hanging"), []);
+        assert_eq!(features("black-hanging"), [Square::H5]);
+        assert!(features("white-attacks").contains(&Square::H5));
+        assert!(features("black-attacks").contains(&Square::D2));
+
+        // The knights and the rook reach four squares or more, only the bishop reaches eight.
+        let four = features("mobility-4");
+        for square in [Square::D2, Square::H1, Square::H5] {
+            assert!(four.contains(&square));
+        }
+        assert_eq!(features("mobility-8"), [Square::B4]);
+        assert_eq!(features("mobility-1").len(), 6);
+    }
+
+    #[test]
+    fn checks_and_king_zones() {
+        // The undefended rook checks the king next to it.
+        let features = features("4k3/8/8/8/8/8/4r3/4K3 w - - 0 1");
+        assert_eq!(features("checkers"), [Square::E2]);
+        assert_eq!(features("black-hanging"), [Square::E2]);
+        assert_eq!(
+            features("white-king-zone-attacked"),
+            [Square::E1, Square::D2, Square::F2]
+        );
+        assert_eq!(features("black-king-zone-attacked"), []);
+    }
+}
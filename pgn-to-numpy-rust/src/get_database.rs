@@ -1,7 +1,6 @@
 use std::io::Write;
 use std::io::Read;
-use std::io::Seek;
-use std::{error::Error, str::FromStr, io::SeekFrom};
+use std::{error::Error, str::FromStr};
 use fs_err::File;
 
 use clap::ArgMatches;
@@ -0,0 +1,199 @@
+use shakmaty::{zobrist::ZobristHash, Board, Move, Position};
+
+use crate::{
+    board_to_planes, castling_move_to_output, flip_output, manifest::Castling, mirror_board,
+    mirror_output, PLANES,
+};
+
+/// What is known about the moves that led to a position.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    /// The boards before the position, most recent first.
+    pub boards: Vec<Board>,
+    /// The index (see [`castling_move_to_output`]) of the move that led to the position.
+    pub last_move: Option<u16>,
+    /// How often the position occurred before in the game.
+    pub repetitions: usize,
+}
+
+impl History {
+    /// The history of the position mirrored by [`mirror_chess`](crate::mirror_chess).
+    pub fn mirrored(&self) -> Self {
+        Self {
+            boards: self.boards.iter().map(mirror_board).collect(),
+            last_move: self.last_move.map(mirror_output),
+            repetitions: self.repetitions,
+        }
+    }
+
+    /// The history of the position flipped by [`flip_chess`](crate::flip_chess).
+    pub fn flipped(&self) -> Self {
+        Self {
+            boards: self
+                .boards
+                .iter()
+                .map(|board| {
+                    let mut board = board.clone();
+                    board.flip_horizontal();
+                    board
+                })
+                .collect(),
+            last_move: self.last_move.map(flip_output),
+            repetitions: self.repetitions,
+        }
+    }
+}
+
+/// Records the positions of a game while a visitor replays its moves.
+#[derive(Debug, Clone, Default)]
+pub struct GameHistory {
+    boards: Vec<Board>,
+    hashes: Vec<u64>,
+    last_move: Option<u16>,
+    /// How the last move is indexed, the same as the move labels.
+    castling: Castling,
+}
+
+impl GameHistory {
+    pub fn new(castling: Castling) -> Self {
+        Self {
+            castling,
+            ..Self::default()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.boards.clear();
+        self.hashes.clear();
+        self.last_move = None;
+    }
+
+    /// Records `chess` just before `m` is played in it.
+    pub fn push(&mut self, chess: &(impl Position + ZobristHash), m: &Move) {
+        self.boards.push(chess.board().clone());
+        self.hashes.push(chess.zobrist_hash());
+        self.last_move = Some(castling_move_to_output(m, self.castling));
+    }
+
+    /// The history of the current position, with at most `length` earlier boards.
+    pub fn history(&self, chess: &(impl Position + ZobristHash), length: usize) -> History {
+        let hash: u64 = chess.zobrist_hash();
+        History {
+            boards: self.boards.iter().rev().take(length).cloned().collect(),
+            last_move: self.last_move,
+            repetitions: self.hashes.iter().filter(|&&other| other == hash).count(),
+        }
+    }
+}
+
+/// The names of the history planes for `length` earlier boards, in the order of
+/// [`history_to_planes`]: the pieces of each earlier board, the from and to square of the last
+/// move, and whether the position occurred at least once or twice before.
+pub fn history_planes(length: usize) -> Vec<String> {
+    (1..=length)
+        .flat_map(|age| {
+            PLANES[..2 * 6]
+                .iter()
+                .map(move |plane| format!("history-{age}-{plane}"))
+        })
+        .chain(
+            [
+                "last-move-from",
+                "last-move-to",
+                "repetition-1",
+                "repetition-2",
+            ]
+            .map(String::from),
+        )
+        .collect()
+}
+
+/// Encodes a history as the `count` planes named by [`history_planes`].
+///
+/// Boards before the start of the game are left empty.
+pub fn history_to_planes(history: &History, count: usize) -> Vec<[bool; 64]> {
+    let length = (count - 4) / (2 * 6);
+    let mut planes = vec![[false; 64]; count];
+    for (age, board) in history.boards.iter().take(length).enumerate() {
+        planes[age * 2 * 6..(age + 1) * 2 * 6].copy_from_slice(&board_to_planes(board));
+    }
+
+    let last_move = length * 2 * 6;
+    if let Some(m) = history.last_move {
+        // Drops have no from square.
+        if let Some(from) = planes[last_move].get_mut(m as usize / 64) {
+            *from = true;
+        }
+        planes[last_move + 1][m as usize % 64] = true;
+    }
+    planes[last_move + 2] = [history.repetitions >= 1; 64];
+    planes[last_move + 3] = [history.repetitions >= 2; 64];
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use shakmaty::{fen::Fen, san::San, CastlingMode, Chess, Square};
+
+    use super::*;
+
+    /// Plays the moves from `chess`, recording them in `game`.
+    fn play(game: &mut GameHistory, mut chess: Chess, moves: &[&str]) -> Chess {
+        for san in moves {
+            let m = san
+                .parse::<San>()
+                .expect("valid SAN")
+                .to_move(&chess)
+                .expect("legal move");
+            game.push(&chess, &m);
+            chess = chess.play(&m).expect("legal move");
+        }
+        chess
+    }
+
+    #[test]
+    fn history_to_planes_of_a_repetition() {
+        let mut game = GameHistory::default();
+        let chess = play(&mut game, Chess::default(), &["Nf3", "Nf6", "Ng1", "Ng8"]);
+        let history = game.history(&chess, 8);
+        assert_eq!(history.boards.len(), 4);
+        assert_eq!(history.repetitions, 1);
+
+        let names = history_planes(8);
+        assert_eq!(names.len(), 8 * 2 * 6 + 4);
+        let planes = history_to_planes(&history, names.len());
+        assert_eq!(planes.len(), names.len());
+        let plane = |name: &str| planes[names.iter().position(|other| other == name).unwrap()];
+
+        // The most recent board is the one before Ng8, with the black knight on f6.
+        assert!(plane("history-1-black-knight")[Square::F6 as usize]);
+        assert!(plane("history-4-white-knight")[Square::G1 as usize]);
+        // The boards before the start of the game are empty.
+        assert!(planes[4 * 2 * 6..8 * 2 * 6]
+            .iter()
+            .all(|plane| plane.iter().all(|&value| !value)));
+
+        let squares = |name: &str| plane(name).iter().positions(|&value| value).collect_vec();
+        assert_eq!(squares("last-move-from"), [Square::F6 as usize]);
+        assert_eq!(squares("last-move-to"), [Square::G8 as usize]);
+        assert!(plane("repetition-1").iter().all(|&value| value));
+        assert!(plane("repetition-2").iter().all(|&value| !value));
+    }
+
+    #[test]
+    fn last_move_is_indexed_like_the_labels() {
+        let fen: Fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1".parse().unwrap();
+        let chess: Chess = fen.into_position(CastlingMode::Standard).unwrap();
+        for (castling, to) in [
+            (Castling::KingDestination, Square::G1),
+            (Castling::KingTakesRook, Square::H1),
+        ] {
+            let mut game = GameHistory::new(castling);
+            let after = play(&mut game, chess.clone(), &["O-O"]);
+            let history = game.history(&after, 1);
+            assert_eq!(history.last_move, Some(Square::E1 as u16 * 64 + to as u16));
+            assert_eq!(history.repetitions, 0);
+        }
+    }
+}
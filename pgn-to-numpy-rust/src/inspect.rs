@@ -0,0 +1,190 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{self, Write},
+    ops::Range,
+    path::Path,
+};
+
+use clap::ArgMatches;
+use rand::Rng;
+use shakmaty::{fen::Fen, san::San, uci::Uci, CastlingMode, EnPassantMode};
+
+use neural_chess::{
+    castling_output_to_move,
+    dataset::{chunk_count, ChunkReader, Label},
+    input_to_board, input_to_chess,
+    manifest::{Castling, Manifest},
+    output_to_eval, output_to_uci, INPUT_LENGTH,
+};
+
+pub fn main(options: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let prefix = options.get_one::<String>("dataset").expect("required");
+    let dir = Path::new(options.get_one::<String>("dir").expect("default value"));
+    let castling = Manifest::read(dir, prefix)?.castling;
+    let mut out = io::stdout().lock();
+
+    if let Some(&sample) = options.get_one::<usize>("sample") {
+        let chunks = chunk_count(dir, prefix)?;
+        if chunks == 0 {
+            Err(format!("dataset {prefix} has no files"))?;
+        }
+        let mut rng = rand::thread_rng();
+        // Pick the chunks first, so that each chunk is only opened (and decompressed) once.
+        let mut samples = BTreeMap::<usize, usize>::new();
+        for _ in 0..sample {
+            *samples.entry(rng.gen_range(0..chunks)).or_default() += 1;
+        }
+        for (chunk_index, count) in samples {
+            let mut reader = ChunkReader::open(dir, prefix, chunk_index)?;
+            if reader.rows() == 0 {
+                eprintln!("Chunk {chunk_index} is empty, skipping {count} rows");
+                continue;
+            }
+            let mut rows = (0..count)
+                .map(|_| rng.gen_range(0..reader.rows()))
+                .collect::<Vec<_>>();
+            rows.sort_unstable();
+            for row in rows {
+                let (input, label) = reader.read_row(row)?;
+                write_row(&mut out, chunk_index, row, &input, label, castling)?;
+            }
+        }
+        return Ok(());
+    }
+
+    let chunk_index = *options.get_one::<usize>("file").expect("default value");
+    let rows = parse_rows(options.get_one::<String>("rows").expect("default value"))?;
+
+    let mut reader = ChunkReader::open(dir, prefix, chunk_index)?;
+    for row in rows {
+        let (input, label) = reader.read_row(row)?;
+        write_row(&mut out, chunk_index, row, &input, label, castling)?;
+    }
+
+    Ok(())
+}
+
+/// Parses a single row (`17`) or a half-open range of rows (`10..20`).
+fn parse_rows(rows: &str) -> Result<Range<u64>, Box<dyn Error>> {
+    Ok(match rows.split_once("..") {
+        Some((start, end)) => start.parse()?..end.parse()?,
+        None => {
+            let row = rows.parse()?;
+            row..row + 1
+        }
+    })
+}
+
+fn write_row(
+    out: &mut impl Write,
+    chunk_index: usize,
+    row: u64,
+    input: &[bool; INPUT_LENGTH],
+    label: Label,
+    castling: Castling,
+) -> io::Result<()> {
+    writeln!(out, "Chunk {chunk_index}, row {row}")?;
+
+    let chess = input_to_chess(input);
+    match &chess {
+        Ok(chess) => writeln!(
+            out,
+            "FEN: {}",
+            Fen::from_position(chess.clone(), EnPassantMode::Legal)
+        )?,
+        Err(err) => writeln!(out, "Invalid position: {err}")?,
+    }
+    write!(out, "{:?}", input_to_board(input))?;
+
+    match label {
+        Label::Move(output) => {
+            let Some(uci) = output_to_uci(output) else {
+                return writeln!(out, "Label: {output} (out of range)");
+            };
+            match chess.ok().and_then(|chess| {
+                castling_output_to_move(&chess, output, castling)
+                    .map(|m| (San::from_move(&chess, &m), m))
+            }) {
+                Some((san, m)) => writeln!(
+                    out,
+                    "Label: {} ({san}, index {output})",
+                    Uci::from_move(&m, CastlingMode::Standard)
+                )?,
+                None => writeln!(out, "Label: {uci} (index {output}, not a legal move)")?,
+            }
+        }
+        Label::Eval(output) => {
+            writeln!(
+                out,
+                "Label: {:+.2} (output {output:.4})",
+                output_to_eval(output)
+            )?;
+        }
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use neural_chess::{chess_to_input, DatasetConfig};
+    use shakmaty::Chess;
+
+    use super::*;
+    use crate::testing::{temp_dir, write_dataset};
+
+    #[test]
+    fn rows_and_ranges() {
+        assert_eq!(parse_rows("17").unwrap(), 17..18);
+        assert_eq!(parse_rows("10..20").unwrap(), 10..20);
+        assert!(parse_rows("10..").is_err());
+        assert!(parse_rows("e4").is_err());
+    }
+
+    #[test]
+    fn rows_of_a_written_dataset() {
+        let dir = temp_dir("inspect");
+        let manifest = write_dataset(&dir, DatasetConfig::default());
+        let mut reader = ChunkReader::open(&dir, "opera", 1).expect("second chunk");
+        let mut out = Vec::new();
+        for row in 0..2 {
+            let (input, label) = reader.read_row(row).expect("row");
+            write_row(&mut out, 1, row, &input, label, manifest.castling).expect("written");
+        }
+        fs_err::remove_dir_all(&dir).expect("temporary directory");
+
+        // 3. d4 Bg4, after 1. e4 e5 2. Nf3 d6.
+        let out = String::from_utf8(out).expect("UTF-8");
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "Chunk 1, row 0");
+        assert_eq!(
+            lines[1],
+            "FEN: rnbqkbnr/ppp2ppp/3p4/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 0 1"
+        );
+        assert!(lines.contains(&"Label: d2d4 (d4, index 731)"));
+        assert!(lines.contains(&"Chunk 1, row 1"));
+        assert!(lines.contains(&"Label: c8g4 (Bg4, index 3742)"));
+    }
+
+    #[test]
+    fn labels_that_are_not_legal_moves() {
+        let chess = Chess::default();
+        let input = chess_to_input(&chess);
+        let mut out = Vec::new();
+        // e7e5 is not a move of White, and 5000 is no square pair.
+        for output in [52 * 64 + 36, 5000] {
+            write_row(
+                &mut out,
+                0,
+                0,
+                &input,
+                Label::Move(output),
+                Castling::KingDestination,
+            )
+            .expect("written");
+        }
+        let out = String::from_utf8(out).expect("UTF-8");
+        assert!(out.contains("Label: e7e5 (index 3364, not a legal move)"));
+        assert!(out.contains("Label: 5000 (out of range)"));
+    }
+}
@@ -0,0 +1,90 @@
+use std::{
+    error::Error,
+    io::{BufRead, BufReader, Read},
+};
+
+use clap::ArgMatches;
+use fs_err::File;
+use neural_chess::{
+    eval_database::{EvalRecord, PrincipalVariation},
+    eval_to_output,
+    manifest::Castling,
+    Sample,
+};
+use shakmaty::Chess;
+use zstd::Decoder;
+
+use crate::dataset_writer;
+
+pub fn main(options: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let filename = options.get_one::<String>("jsonl-file").expect("required");
+    let file = File::open(filename)?;
+    // The database is distributed as `lichess_db_eval.jsonl.zst`.
+    let reader: Box<dyn Read> = if filename.ends_with(".zst") {
+        Box::new(Decoder::new(file)?)
+    } else {
+        Box::new(file)
+    };
+
+    // Lines that are not valid records are skipped, but reading stops at the first line that
+    // can not be read at all.
+    let mut error = None;
+    let records = BufReader::new(reader)
+        .lines()
+        .enumerate()
+        .map_while(|(index, line)| line.map_err(|err| error = Some((index + 1, err))).ok())
+        .filter_map(|line| serde_json::from_str::<EvalRecord>(&line).ok());
+
+    let writer = dataset_writer()?;
+    let manifest = writer.manifest();
+    let policy = manifest.policy_temperature.zip(manifest.policy_top_k);
+    let castling = manifest.castling;
+
+    let written = if options.get_flag("best_move") {
+        let samples = records.filter_map(|record| {
+            record_to_sample(&record, policy, castling, |chess, best| {
+                best.first_move(chess)
+            })
+        });
+        writer.write_moves(samples)
+    } else {
+        let samples = records.filter_map(|record| {
+            record_to_sample(&record, policy, castling, |_, best| {
+                best.eval().map(eval_to_output)
+            })
+        });
+        writer.write(samples)
+    };
+    // A read error also cuts the dataset short, so it is the more useful one to report.
+    if let Some((line, err)) = error {
+        Err(format!("{filename}:{line}: {err}"))?;
+    }
+    written?;
+
+    Ok(())
+}
+
+/// Labels the position of a record by the best line of its deepest evaluation, and adds the
+/// soft policy target of its lines if `policy` gives a temperature and `top_k`.
+///
+/// Records without a usable evaluation are skipped.
+fn record_to_sample<T>(
+    record: &EvalRecord,
+    policy: Option<(f32, usize)>,
+    castling: Castling,
+    label: impl Fn(&Chess, &PrincipalVariation) -> Option<T>,
+) -> Option<Sample<T>> {
+    let chess = record.position()?;
+    let evaluation = record.deepest()?;
+    let label = label(&chess, evaluation.pvs.first()?)?;
+    let policy = match policy {
+        Some((temperature, top_k)) => {
+            Some(evaluation.policy(&chess, castling, temperature, top_k)?)
+        }
+        None => None,
+    };
+    Some(Sample {
+        policy,
+        ..Sample::new(chess, label)
+    })
+}
@@ -0,0 +1,32 @@
+//! Encoders, label mappers, PGN visitors and dataset writers for neural chess training data.
+//!
+//! The `pgn-to-numpy` binary is a thin command line wrapper around `DatasetWriter`. Everything
+//! that reads PGN or touches the file system needs the default `datasets` feature.
+
+#[cfg(feature = "datasets")]
+mod backend;
+mod common;
+#[cfg(feature = "datasets")]
+pub mod dataset;
+#[cfg(feature = "datasets")]
+pub mod engine;
+#[cfg(feature = "datasets")]
+pub mod eval_database;
+pub mod features;
+pub mod history;
+pub mod manifest;
+pub mod nnue;
+pub mod policy;
+pub mod variant;
+#[cfg(feature = "datasets")]
+pub mod visitor;
+#[cfg(feature = "datasets")]
+mod writer;
+
+#[cfg(feature = "datasets")]
+pub use backend::OutputLabel;
+pub use common::*;
+#[cfg(feature = "datasets")]
+pub use writer::{
+    DatasetConfig, DatasetError, DatasetWriter, DatasetWriterBuilder, InputConfig, PolicyConfig,
+};
@@ -1,16 +1,25 @@
 use std::{error::Error, process::exit};
 
-use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use lazy_static::lazy_static;
+use neural_chess::{
+    dataset::NPY_FILES_DIR,
+    manifest::{Castling, Compression, Encoding, Format, Layout, Packing, Perspective, Variant},
+    DatasetConfig, DatasetError, DatasetWriter, InputConfig, PolicyConfig,
+};
 
 // mod intersperse;
-mod common;
+mod annotate;
 mod csv_to_numpy;
 mod get_database;
+mod inspect;
+mod jsonl_to_eval;
 mod pgn_to_numpy;
 mod pgn_to_numpy_eval;
-
-pub use common::*;
+mod stats;
+#[cfg(test)]
+mod testing;
+mod validate;
 
 lazy_static! {
     pub static ref ARGS: ArgMatches = cli().get_matches();
@@ -22,6 +31,15 @@ fn cli() -> Command {
         .short('f')
         .required(true)
         .default_value("database-2017-01.pgn");
+    let dataset_arg = Arg::new("dataset")
+        .long("dataset")
+        .short('d')
+        .required(true)
+        .help("Dataset prefix, as given to --output");
+    let dir_arg = Arg::new("dir")
+        .long("dir")
+        .default_value(NPY_FILES_DIR)
+        .help("Directory of the dataset, as given to --output-dir");
     Command::new("rust-neural-chess")
         .author("Leo Blume")
         .about("Tools to create neural training data for the board game chess.")
@@ -39,6 +57,12 @@ fn cli() -> Command {
                 .short('o')
                 .help("Output directory prefix"),
         )
+        .arg(
+            Arg::new("output_dir")
+                .long("output-dir")
+                .default_value(NPY_FILES_DIR)
+                .help("Directory the output dataset is written to"),
+        )
         .arg(
             Arg::new("total")
                 .long("total")
@@ -55,6 +79,126 @@ fn cli() -> Command {
                 .default_value("500000")
                 .help("Boards per file"),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(["npy", "npz", "tfrecord", "parquet", "arrow"])
+                .default_value("npy")
+                .help("Storage format of the dataset"),
+        )
+        .arg(
+            Arg::new("compression")
+                .long("compression")
+                .value_parser(["none", "deflate", "zstd", "gzip"])
+                .default_value("none")
+                .help("Compression of the chunks (deflate and zstd for npz, gzip for tfrecord)"),
+        )
+        .arg(
+            Arg::new("encoding")
+                .long("encoding")
+                .value_parser(["board", "halfkp", "halfkav2"])
+                .default_value("board")
+                .help("Encode positions as boards, or as the active HalfKP / HalfKAv2 features of NNUE networks (npy and npz only)"),
+        )
+        .arg(
+            Arg::new("perspective")
+                .long("perspective")
+                .value_parser(["white", "side-to-move"])
+                .default_value("white")
+                .help("Encode positions as they are, or mirrored so that the side to move is always White"),
+        )
+        .arg(
+            Arg::new("mirror_augmentation")
+                .long("mirror-augmentation")
+                .value_name("PROBABILITY")
+                .value_parser(value_parser!(f64))
+                .help("Also add a copy mirrored along the a-h axis of positions without castling rights, with this probability"),
+        )
+        .arg(
+            Arg::new("layout")
+                .long("layout")
+                .value_parser(["flat", "planes-first", "planes-last"])
+                .default_value("flat")
+                .help("Store the inputs as flat vectors or as (N, planes, 8, 8) / (N, 8, 8, planes) tensors (npy and npz only)"),
+        )
+        .arg(
+            Arg::new("policy_top_k")
+                .long("policy-top-k")
+                .value_name("K")
+                .value_parser(value_parser!(usize))
+                .help("Aggregate identical positions and also write the K most frequent moves and their probabilities (npy and npz only)"),
+        )
+        .arg(
+            Arg::new("policy_elo_weighting")
+                .long("policy-elo-weighting")
+                .requires("policy_top_k")
+                .help("Weight the moves of the soft policy targets by the Elo of the player to move")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("policy_temperature")
+                .long("policy-temperature")
+                .value_name("T")
+                .value_parser(value_parser!(f32))
+                .requires("policy_top_k")
+                .conflicts_with("policy_elo_weighting")
+                .help("Take the soft policy targets from the softmax of the scores of the K best engine lines in pawns, divided by T, instead of the played moves (jsonl-to-eval and annotate only)"),
+        )
+        .arg(
+            Arg::new("tactical_features")
+                .long("tactical-features")
+                .help("Append planes of attacked squares, pins, checks, hanging pieces, king zone attacks and mobility to the inputs (npy and npz only)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .help("Also write history planes: the pieces of the N previous positions, the last move and the repetition count (npy and npz only)"),
+        )
+        .arg(
+            Arg::new("legal_moves")
+                .long("legal-moves")
+                .help("Also write a bit-packed mask of the legal move indices of each position (npy and npz only)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("castling")
+                .long("castling")
+                .value_parser(["king-destination", "king-takes-rook"])
+                .default_value("king-destination")
+                .help("Label castling moves by the king's destination square, or by the square of the castling rook (unambiguous in Chess960)"),
+        )
+        .arg(
+            Arg::new("variant")
+                .long("variant")
+                .value_parser([
+                    "chess",
+                    "atomic",
+                    "antichess",
+                    "king-of-the-hill",
+                    "three-check",
+                    "crazyhouse",
+                    "racing-kings",
+                    "horde",
+                ])
+                .default_value("chess")
+                .help("Only convert games of this Lichess variant; crazyhouse and three-check also write pockets or remaining checks (npy and npz only)"),
+        )
+        .arg(
+            Arg::new("packed")
+                .long("packed")
+                .help("Store the inputs bit-packed, eight values per byte (see np.unpackbits)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sparse")
+                .long("sparse")
+                .help("Store the indices of the set input values instead of the inputs (npy and npz only)")
+                .conflicts_with("packed")
+                .action(ArgAction::SetTrue),
+        )
         .subcommand_required(true)
         .subcommand(
             Command::new("pgn-to-npy")
@@ -66,6 +210,85 @@ fn cli() -> Command {
                 .about("Convert a PGN database to evaluation data")
                 .arg(pgn_arg.clone()),
         )
+        .subcommand(
+            Command::new("jsonl-to-eval")
+                .about("Convert the Lichess evaluation database to evaluation data")
+                .arg(
+                    Arg::new("jsonl-file")
+                        .long("jsonl-file")
+                        .short('f')
+                        .default_value("lichess_db_eval.jsonl")
+                        .help("The database, optionally compressed with zstd (.jsonl.zst)"),
+                )
+                .arg(
+                    Arg::new("best_move")
+                        .long("best-move")
+                        .help("Label the positions with the first move of the deepest evaluation's best line instead of its evaluation")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("annotate")
+                .about("Label the positions of a PGN database or dataset with a UCI engine")
+                .arg(
+                    Arg::new("engine")
+                        .long("engine")
+                        .short('e')
+                        .required(true)
+                        .help("Path of the UCI engine executable"),
+                )
+                .arg(
+                    Arg::new("pgn-file")
+                        .long("pgn-file")
+                        .short('f')
+                        .help("Annotate the positions of the games of this PGN database"),
+                )
+                .arg(
+                    Arg::new("dataset")
+                        .long("dataset")
+                        .short('d')
+                        .help("Annotate the positions of this dataset, as given to --output"),
+                )
+                .arg(dir_arg.clone())
+                .group(
+                    ArgGroup::new("source")
+                        .args(["pgn-file", "dataset"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("depth")
+                        .long("depth")
+                        .value_parser(value_parser!(u32))
+                        .default_value("12")
+                        .help("Search each position to this depth"),
+                )
+                .arg(
+                    Arg::new("nodes")
+                        .long("nodes")
+                        .value_parser(value_parser!(u64))
+                        .help("Search each position for this many nodes instead of a fixed depth"),
+                )
+                .arg(
+                    Arg::new("engines")
+                        .long("engines")
+                        .short('j')
+                        .value_parser(value_parser!(usize))
+                        .help("Number of engine processes, by default one per CPU"),
+                )
+                .arg(
+                    Arg::new("sample_rate")
+                        .long("sample-rate")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0")
+                        .help("Annotate each position with this probability"),
+                )
+                .arg(
+                    Arg::new("best_move")
+                        .long("best-move")
+                        .help("Label the positions with the engine's best move instead of its evaluation")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .subcommand(
             Command::new("csv-to-npy")
                 .about("Convert a CSV database to training data")
@@ -76,6 +299,63 @@ fn cli() -> Command {
                         .required(true),
                 ),
         )
+        .subcommand(
+            Command::new("inspect")
+                .about("Decode and display rows of a dataset")
+                .arg(dataset_arg.clone())
+                .arg(dir_arg.clone())
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .short('f')
+                        .value_parser(value_parser!(usize))
+                        .default_value("0")
+                        .help("Index of the file to read"),
+                )
+                .arg(
+                    Arg::new("rows")
+                        .long("rows")
+                        .short('r')
+                        .default_value("0")
+                        .help("Row or range of rows to display, e.g. 17 or 10..20"),
+                )
+                .arg(
+                    Arg::new("sample")
+                        .long("sample")
+                        .short('s')
+                        .value_parser(value_parser!(usize))
+                        .conflicts_with_all(["file", "rows"])
+                        .help("Display this many random rows from all files instead"),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Report label and position distributions of a dataset")
+                .arg(dataset_arg.clone())
+                .arg(dir_arg.clone())
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Write the reports to this JSON file instead of printing them"),
+                )
+                .arg(
+                    Arg::new("csv")
+                        .long("csv")
+                        .help("Write the reports to this CSV file instead of printing them"),
+                )
+                .arg(
+                    Arg::new("duplicates")
+                        .long("duplicates")
+                        .help("Also count duplicated positions; keeps a hash of every row, so memory grows with the row count")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check the integrity of a dataset")
+                .arg(dataset_arg.clone())
+                .arg(dir_arg.clone()),
+        )
         .subcommand(
             Command::new("get-database")
                 .about("Download a Lichess database from the internet")
@@ -87,6 +367,89 @@ fn cli() -> Command {
         )
 }
 
+/// Creates the dataset writer configured by the global options.
+pub fn dataset_writer() -> Result<DatasetWriter, DatasetError> {
+    let prefix = ARGS
+        .get_one::<String>("output")
+        .expect("No output directory specified");
+    let choice = |id: &str| ARGS.get_one::<String>(id).expect("default value").as_str();
+
+    let config = DatasetConfig {
+        total: *ARGS
+            .get_one::<usize>("total")
+            .expect("No total data specified"),
+        boards_per_file: *ARGS
+            .get_one::<usize>("boards_per_file")
+            .expect("No boards per file specified"),
+        format: match choice("format") {
+            "npz" => Format::Npz,
+            "tfrecord" => Format::Tfrecord,
+            "parquet" => Format::Parquet,
+            "arrow" => Format::Arrow,
+            _ => Format::Npy,
+        },
+        compression: match choice("compression") {
+            "deflate" => Compression::Deflate,
+            "zstd" => Compression::Zstd,
+            "gzip" => Compression::Gzip,
+            _ => Compression::None,
+        },
+        input: InputConfig {
+            encoding: match choice("encoding") {
+                "halfkp" => Encoding::HalfKp,
+                "halfkav2" => Encoding::HalfKaV2,
+                _ => Encoding::Board,
+            },
+            packing: if ARGS.get_flag("packed") {
+                Packing::Packbits
+            } else if ARGS.get_flag("sparse") {
+                Packing::Sparse
+            } else {
+                Packing::None
+            },
+            layout: match choice("layout") {
+                "planes-first" => Layout::PlanesFirst,
+                "planes-last" => Layout::PlanesLast,
+                _ => Layout::Flat,
+            },
+            perspective: match choice("perspective") {
+                "side-to-move" => Perspective::SideToMove,
+                _ => Perspective::White,
+            },
+            tactical_features: ARGS.get_flag("tactical_features"),
+        },
+        mirror_augmentation: ARGS.get_one::<f64>("mirror_augmentation").copied(),
+        legal_moves: ARGS.get_flag("legal_moves"),
+        castling: match choice("castling") {
+            "king-takes-rook" => Castling::KingTakesRook,
+            _ => Castling::KingDestination,
+        },
+        variant: match choice("variant") {
+            "atomic" => Variant::Atomic,
+            "antichess" => Variant::Antichess,
+            "king-of-the-hill" => Variant::KingOfTheHill,
+            "three-check" => Variant::ThreeCheck,
+            "crazyhouse" => Variant::Crazyhouse,
+            "racing-kings" => Variant::RacingKings,
+            "horde" => Variant::Horde,
+            _ => Variant::Chess,
+        },
+        history: ARGS.get_one::<usize>("history").copied(),
+        policy: ARGS
+            .get_one::<usize>("policy_top_k")
+            .map(|&top_k| PolicyConfig {
+                top_k,
+                elo_weighting: ARGS.get_flag("policy_elo_weighting"),
+                temperature: ARGS.get_one::<f32>("policy_temperature").copied(),
+            }),
+        progress: true,
+    };
+    DatasetWriter::builder(prefix)
+        .output_dir(choice("output_dir"))
+        .config(config)
+        .build()
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     match ARGS.subcommand() {
         Some(("pgn-to-npy", matches)) => {
@@ -95,9 +458,24 @@ fn main() -> Result<(), Box<dyn Error>> {
         Some(("pgn-to-eval", matches)) => {
             pgn_to_numpy_eval::main(matches)?;
         }
+        Some(("jsonl-to-eval", matches)) => {
+            jsonl_to_eval::main(matches)?;
+        }
+        Some(("annotate", matches)) => {
+            annotate::main(matches)?;
+        }
         Some(("csv-to-npy", matches)) => {
             csv_to_numpy::main(matches)?;
         }
+        Some(("inspect", matches)) => {
+            inspect::main(matches)?;
+        }
+        Some(("stats", matches)) => {
+            stats::main(matches)?;
+        }
+        Some(("validate", matches)) => {
+            validate::main(matches)?;
+        }
         Some(("get-database", matches)) => {
             get_database::main(matches)?;
         }
@@ -0,0 +1,269 @@
+#[cfg(feature = "datasets")]
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "datasets")]
+use fs_err::{self as fs, File};
+use serde::{Deserialize, Serialize};
+
+use crate::{nnue::NNUE_MAX_FEATURES, INPUT_LENGTH, SPARSE_INPUT_LENGTH};
+
+/// The storage format of a dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Paired `.npy` files in `<prefix>_input` and `<prefix>_output`.
+    #[default]
+    Npy,
+    /// One `.npz` archive per chunk in `<prefix>_npz`, with an `input` and an `output` array.
+    Npz,
+    /// One TFRecord file of `tf.train.Example`s per chunk in `<prefix>_tfrecord`, with bit-packed
+    /// inputs.
+    Tfrecord,
+    /// One Parquet file per chunk in `<prefix>_parquet`, with bit-packed inputs.
+    Parquet,
+    /// One Arrow IPC file per chunk in `<prefix>_arrow`, with bit-packed inputs.
+    Arrow,
+}
+
+/// How the inputs of a dataset are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Packing {
+    /// One `bool` per input value.
+    #[default]
+    None,
+    /// Eight input values per `u8`, most significant bit first, as written by `np.packbits`.
+    Packbits,
+    /// The indices of the set input values as `i16`, padded with `-1` to `sparse_width`.
+    Sparse,
+}
+
+/// The shape of the stored inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// One flat vector of `input_length` values per position.
+    #[default]
+    Flat,
+    /// `(N, planes, 8, 8)` tensors, as used by channels-first convolutions.
+    PlanesFirst,
+    /// `(N, 8, 8, planes)` tensors, as used by channels-last convolutions (the Keras default).
+    PlanesLast,
+}
+
+/// How positions are turned into inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Encoding {
+    /// The side to move and one-hot pieces per square, see [`chess_to_input`](crate::chess_to_input).
+    #[default]
+    #[serde(rename = "board")]
+    Board,
+    /// `(N, 2, 32)` active HalfKP feature indices of White's and Black's perspective, padded with
+    /// `-1`, with the side to move in a separate `turn` array.
+    #[serde(rename = "halfkp")]
+    HalfKp,
+    /// Like `HalfKp`, with the HalfKAv2 feature set.
+    #[serde(rename = "halfkav2")]
+    HalfKaV2,
+}
+
+/// Whose point of view the positions are encoded from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Perspective {
+    /// Positions are stored as they are.
+    #[default]
+    White,
+    /// Positions with Black to move are mirrored (see [`mirror_chess`](crate::mirror_chess)),
+    /// so White is always to move. Move and evaluation labels are mirrored as well.
+    SideToMove,
+}
+
+/// How castling moves are turned into move indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Castling {
+    /// From the king to its destination square, g1 or c1 for White. In standard chess this is
+    /// the UCI notation of the move.
+    #[default]
+    KingDestination,
+    /// From the king to the castling rook, like UCI in Chess960. Unlike the king destination,
+    /// this can not coincide with a normal king move in Chess960 positions.
+    KingTakesRook,
+}
+
+/// The chess variant of the positions, one of the variants played on Lichess.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Variant {
+    /// Standard chess and Chess960.
+    #[default]
+    Chess,
+    Atomic,
+    Antichess,
+    KingOfTheHill,
+    /// Also writes the remaining checks of both sides, see [`variant_inputs`](crate::variant::variant_inputs).
+    ThreeCheck,
+    /// Also writes the pockets of both sides. Drops get move indices after the normal moves,
+    /// see [`move_to_output`](crate::move_to_output).
+    Crazyhouse,
+    RacingKings,
+    Horde,
+}
+
+/// How the chunks of an npz or TFRecord dataset are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    #[default]
+    None,
+    /// Deflate-compressed archive entries, as written by `np.savez_compressed`.
+    Deflate,
+    /// An uncompressed archive, compressed as a whole with zstd (`<chunk>.npz.zst`).
+    Zstd,
+    /// A gzip-compressed TFRecord file (`<chunk>.tfrecord.gz`).
+    Gzip,
+}
+
+/// Describes how a dataset was written, stored next to its input and output directories.
+///
+/// Datasets without a manifest were written before it existed and use the defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    pub format: Format,
+    pub encoding: Encoding,
+    /// The length of a flat input: the position encoding followed by the feature planes.
+    pub input_length: usize,
+    pub packing: Packing,
+    pub compression: Compression,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparse_width: Option<usize>,
+    pub perspective: Perspective,
+    /// The probability with which a horizontally mirrored copy was added for each position.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror_augmentation: Option<f64>,
+    pub layout: Layout,
+    /// The names of the planes of a plane layout, in order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub planes: Option<Vec<String>>,
+    /// The names of the tactical feature planes appended to the inputs, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+    /// The names of the history planes, if they were written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<String>>,
+    /// The number of moves of the soft policy targets, if they were written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_top_k: Option<usize>,
+    /// Whether the soft policy targets are weighted by the Elo of the player to move.
+    pub policy_elo_weighting: bool,
+    /// The softmax temperature of soft policy targets from engine lines instead of played moves.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_temperature: Option<f32>,
+    /// Whether a packed legal move mask was written for every position.
+    pub legal_moves: bool,
+    pub castling: Castling,
+    pub variant: Variant,
+    /// The names of the columns of the `variant` array, for variants with inputs beyond the board.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant_inputs: Option<Vec<String>>,
+    pub boards_per_file: usize,
+    pub files: usize,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            format: Format::Npy,
+            encoding: Encoding::Board,
+            input_length: INPUT_LENGTH,
+            packing: Packing::None,
+            compression: Compression::None,
+            sparse_width: None,
+            perspective: Perspective::White,
+            mirror_augmentation: None,
+            layout: Layout::Flat,
+            planes: None,
+            features: None,
+            history: None,
+            policy_top_k: None,
+            policy_elo_weighting: false,
+            policy_temperature: None,
+            legal_moves: false,
+            castling: Castling::KingDestination,
+            variant: Variant::Chess,
+            variant_inputs: None,
+            boards_per_file: 0,
+            files: 0,
+        }
+    }
+}
+
+impl Manifest {
+    /// The path of the manifest of a dataset in `dir`.
+    #[cfg(feature = "datasets")]
+    pub fn path(dir: &Path, prefix: &str) -> PathBuf {
+        dir.join(prefix.to_owned() + "_manifest.json")
+    }
+
+    /// Reads the manifest of a dataset, falling back to the defaults if there is none.
+    #[cfg(feature = "datasets")]
+    pub fn read(dir: &Path, prefix: &str) -> io::Result<Self> {
+        let path = Self::path(dir, prefix);
+        if !path.try_exists()? {
+            return Ok(Self::default());
+        }
+        let manifest = fs::read_to_string(path)?;
+        serde_json::from_str(&manifest)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    #[cfg(feature = "datasets")]
+    pub fn write(&self, dir: &Path, prefix: &str) -> io::Result<()> {
+        let file = File::create(Self::path(dir, prefix))?;
+        serde_json::to_writer_pretty(file, self).map_err(io::Error::from)
+    }
+
+    /// Whether the inputs have the length of the encoding and the feature planes.
+    pub fn has_valid_input_length(&self) -> bool {
+        let features = self.features.as_ref().map_or(0, Vec::len);
+        self.input_length == INPUT_LENGTH + features * 64
+    }
+
+    /// Number of columns of the stored input arrays.
+    pub fn input_columns(&self) -> usize {
+        match self.packing {
+            Packing::None => self.input_length,
+            Packing::Packbits => self.input_length.div_ceil(8),
+            Packing::Sparse => self.sparse_width.unwrap_or(SPARSE_INPUT_LENGTH),
+        }
+    }
+
+    /// Shape of one stored input, i.e. the input array shape without the leading `N`.
+    pub fn input_shape(&self) -> Vec<u64> {
+        if self.encoding != Encoding::Board {
+            return vec![2, NNUE_MAX_FEATURES as u64];
+        }
+        let planes = self.planes.as_ref().map_or(0, Vec::len) as u64;
+        match self.layout {
+            Layout::Flat => vec![self.input_columns() as u64],
+            Layout::PlanesFirst => vec![planes, 8, 8],
+            Layout::PlanesLast => vec![8, 8, planes],
+        }
+    }
+
+    /// Shape of the history planes of one position, in the layout of the inputs. A flat layout
+    /// stores them plane by plane.
+    pub fn history_shape(&self) -> Option<Vec<u64>> {
+        let planes = self.history.as_ref()?.len() as u64;
+        Some(match self.layout {
+            Layout::Flat => vec![planes * 64],
+            Layout::PlanesFirst => vec![planes, 8, 8],
+            Layout::PlanesLast => vec![8, 8, planes],
+        })
+    }
+}
@@ -0,0 +1,116 @@
+use shakmaty::{Color, Piece, Position, Role, Square};
+
+use crate::manifest::Encoding;
+
+/// The most features a perspective can have: one per piece on the board.
+pub const NNUE_MAX_FEATURES: usize = 32;
+
+/// Squares times non-king piece types of both colours, plus the unused index 0.
+const HALFKP_PLANES: usize = 64 * 10 + 1;
+/// Squares times piece types of both colours, with both kings sharing one type.
+const HALFKAV2_PLANES: usize = 64 * 11;
+
+/// The number of distinct feature indices of an NNUE encoding.
+pub fn feature_count(encoding: Encoding) -> usize {
+    match encoding {
+        Encoding::Board => 0,
+        Encoding::HalfKp => 64 * HALFKP_PLANES,
+        Encoding::HalfKaV2 => 64 * HALFKAV2_PLANES,
+    }
+}
+
+/// The active feature indices of both perspectives (White first), padded with `-1`.
+///
+/// The indices match the `HalfKP` and `HalfKAv2` feature sets of nnue-pytorch. HalfKP
+/// rotates the board for Black's perspective and leaves out the kings, HalfKAv2 flips it
+/// vertically and includes them.
+pub fn chess_to_features(
+    chess: &impl Position,
+    encoding: Encoding,
+) -> [[i32; NNUE_MAX_FEATURES]; 2] {
+    Color::ALL.map(|perspective| {
+        let mut features = [-1; NNUE_MAX_FEATURES];
+        let Some(king) = chess.board().king_of(perspective) else {
+            return features;
+        };
+        let pieces = chess
+            .board()
+            .clone()
+            .into_iter()
+            .filter_map(|(square, piece)| {
+                feature_index(encoding, perspective, king, square, piece)
+            });
+        for (slot, index) in features.iter_mut().zip(pieces) {
+            *slot = index as i32;
+        }
+        features
+    })
+}
+
+fn feature_index(
+    encoding: Encoding,
+    perspective: Color,
+    king: Square,
+    square: Square,
+    Piece { color, role }: Piece,
+) -> Option<usize> {
+    let piece = (u32::from(role) as usize - 1) * 2 + usize::from(color != perspective);
+    match encoding {
+        Encoding::Board => None,
+        Encoding::HalfKp if role == Role::King => None,
+        Encoding::HalfKp => {
+            let orient = |square: Square| match perspective {
+                Color::White => square as usize,
+                Color::Black => square as usize ^ 63,
+            };
+            Some(1 + orient(square) + piece * 64 + orient(king) * HALFKP_PLANES)
+        }
+        Encoding::HalfKaV2 => {
+            let orient = |square: Square| match perspective {
+                Color::White => square as usize,
+                Color::Black => square as usize ^ 56,
+            };
+            Some(orient(square) + piece.min(10) * 64 + orient(king) * HALFKAV2_PLANES)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::{fen::Fen, CastlingMode, Chess};
+
+    use super::*;
+
+    /// The sorted features of both perspectives.
+    fn features(fen: &str, encoding: Encoding) -> [Vec<i32>; 2] {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        let chess: Chess = fen
+            .into_position(CastlingMode::Standard)
+            .expect("legal position");
+        chess_to_features(&chess, encoding).map(|features| {
+            let mut features: Vec<i32> = features.into_iter().filter(|&i| i >= 0).collect();
+            features.sort_unstable();
+            features
+        })
+    }
+
+    // Kings on e1 and e8, a white pawn on e2 and a black knight on c6. The indices are those
+    // of `halfkp_idx` and `halfka_idx` in nnue-pytorch.
+    const POSITION: &str = "4k3/8/2n5/8/8/8/4P3/4K3 w - - 0 1";
+
+    #[test]
+    fn halfkp_features() {
+        assert_eq!(
+            features(POSITION, Encoding::HalfKp),
+            [vec![2577, 2799], vec![2039, 2073]]
+        );
+    }
+
+    #[test]
+    fn halfkav2_features() {
+        assert_eq!(
+            features(POSITION, Encoding::HalfKaV2),
+            [vec![2828, 3050, 3460, 3516], vec![2932, 2962, 3460, 3516]]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+#[cfg(feature = "datasets")]
+use std::collections::HashMap;
+
+#[cfg(feature = "datasets")]
+use shakmaty::{Color, Position};
+
+#[cfg(feature = "datasets")]
+use crate::{
+    backend::OutputLabel, chess_to_input, dataset::Label, pack_input,
+    variant::position_to_variant_inputs, DatasetError, Sample,
+};
+
+/// Move indices with their probabilities, most likely move first.
+pub type Policy = Vec<(u16, f32)>;
+
+/// The summed weight of every move played in a position, with the label of one occurrence.
+#[cfg(feature = "datasets")]
+type MoveWeights<T> = HashMap<u16, (f64, T)>;
+
+/// Elo assumed for players without a rating when weighting by Elo.
+#[cfg(feature = "datasets")]
+const DEFAULT_ELO: f64 = 1500.0;
+
+/// Groups all samples of identical positions and gives each position a soft policy target: the
+/// `top_k` most played moves with their (renormalized) frequencies.
+///
+/// The label becomes the most played move. Positions keep the order in which they were first
+/// seen. As this has to see the whole corpus first, all positions are kept in memory.
+#[cfg(feature = "datasets")]
+pub fn aggregate<T: OutputLabel>(
+    samples: impl Iterator<Item = Sample<T>>,
+    top_k: usize,
+    elo_weighting: bool,
+) -> Result<Vec<Sample<T>>, DatasetError> {
+    let mut positions: Vec<(Sample<T>, MoveWeights<T>)> = Vec::new();
+    let mut indices: HashMap<u64, usize> = HashMap::new();
+
+    for sample in samples {
+        let Label::Move(index) = sample.label.into() else {
+            return Err(DatasetError::InvalidConfig(
+                "soft policy targets need move labels".to_owned(),
+            ));
+        };
+        let weight = if elo_weighting {
+            let game = sample.game.as_deref();
+            let elo = match sample.chess.turn() {
+                Color::White => game.and_then(|game| game.white_elo),
+                Color::Black => game.and_then(|game| game.black_elo),
+            };
+            elo.map_or(DEFAULT_ELO, f64::from)
+        } else {
+            1.0
+        };
+
+        let mut bytes = pack_input(&chess_to_input(&sample.chess)).to_vec();
+        bytes.extend(position_to_variant_inputs(&sample.chess));
+        let key = stable_hash(&bytes);
+        let label = sample.label;
+        let position = *indices.entry(key).or_insert_with(|| {
+            positions.push((sample, HashMap::new()));
+            positions.len() - 1
+        });
+        positions[position].1.entry(index).or_insert((0.0, label)).0 += weight;
+    }
+
+    Ok(positions
+        .into_iter()
+        .map(|(mut sample, moves)| {
+            let mut moves: Vec<_> = moves.into_iter().collect();
+            moves.sort_by(|(a, (a_weight, _)), (b, (b_weight, _))| {
+                b_weight.total_cmp(a_weight).then(a.cmp(b))
+            });
+            moves.truncate(top_k);
+
+            let total: f64 = moves.iter().map(|(_, (weight, _))| weight).sum();
+            sample.label = moves[0].1 .1;
+            sample.policy = Some(
+                moves
+                    .iter()
+                    .map(|&(index, (weight, _))| (index, (weight / total) as f32))
+                    .collect(),
+            );
+            sample
+        })
+        .collect())
+}
+
+/// Forced mates count as this many pawns in [`Score::pawns`], less one per move until mate.
+pub const MATE_PAWNS: f32 = 100.0;
+
+/// An engine score of a move, from the point of view of the side to move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    /// Moves until mate, negative if the side to move gets mated.
+    Mate(i32),
+}
+
+impl Score {
+    /// The score in pawns. Forced mates are worth [`MATE_PAWNS`], so that the faster of two
+    /// mates scores higher.
+    pub fn pawns(self) -> f32 {
+        match self {
+            Score::Centipawns(centipawns) => centipawns as f32 / 100.0,
+            Score::Mate(moves) if moves > 0 => MATE_PAWNS - moves as f32,
+            Score::Mate(moves) => -MATE_PAWNS - moves as f32,
+        }
+    }
+}
+
+/// Turns the engine scores of several moves, e.g. of multi-PV lines, into a soft policy target:
+/// the softmax of their scores in pawns divided by `temperature`, most likely move first.
+pub fn softmax(scores: &[(u16, Score)], temperature: f32) -> Policy {
+    let best = scores
+        .iter()
+        .map(|(_, score)| score.pawns())
+        .fold(f32::NEG_INFINITY, f32::max);
+    let weights: Vec<_> = scores
+        .iter()
+        .map(|&(index, score)| (index, ((score.pawns() - best) / temperature).exp()))
+        .collect();
+    let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+    let mut policy: Policy = weights
+        .into_iter()
+        .map(|(index, weight)| (index, weight / total))
+        .collect();
+    policy.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    policy
+}
+
+/// Applies a transformation of move indices, like mirroring, to a policy.
+pub fn map_moves(policy: &Policy, f: impl Fn(u16) -> u16) -> Policy {
+    policy
+        .iter()
+        .map(|&(index, probability)| (f(index), probability))
+        .collect()
+}
+
+/// FNV-1a, which unlike the std hashers gives the same result across runs and Rust versions.
+#[cfg(feature = "datasets")]
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(all(test, feature = "datasets"))]
+mod tests {
+    use std::sync::Arc;
+
+    use shakmaty::Chess;
+
+    use super::*;
+    use crate::GameInfo;
+
+    const E4: u16 = 12 * 64 + 28;
+    const D4: u16 = 11 * 64 + 27;
+    const E5: u16 = 52 * 64 + 36;
+
+    /// A sample of `chess` whose move was played by a player with the given Elo, if any.
+    fn played(chess: Chess, label: u16, elo: Option<u16>) -> Sample<u16> {
+        Sample {
+            game: Some(Arc::new(GameInfo {
+                white_elo: elo,
+                black_elo: elo,
+                ..GameInfo::default()
+            })),
+            ..Sample::new(chess, label)
+        }
+    }
+
+    fn after_e4() -> Chess {
+        let start = Chess::default();
+        let m = start
+            .legal_moves()
+            .into_iter()
+            .find(|m| crate::move_to_output(m) == E4)
+            .expect("e2e4");
+        start.play(&m).expect("legal")
+    }
+
+    #[test]
+    fn aggregate_groups_identical_positions() {
+        let samples = vec![
+            played(Chess::default(), D4, None),
+            played(after_e4(), E5, None),
+            played(Chess::default(), E4, None),
+            played(Chess::default(), E4, None),
+            played(Chess::default(), E4, None),
+        ];
+        let positions = aggregate(samples.clone().into_iter(), 2, false).expect("move labels");
+        assert_eq!(positions.len(), 2);
+        // The starting position comes first, as it was seen first.
+        assert_eq!(positions[0].label, E4);
+        assert_eq!(positions[0].policy, Some(vec![(E4, 0.75), (D4, 0.25)]));
+        assert_eq!(positions[1].label, E5);
+        assert_eq!(positions[1].policy, Some(vec![(E5, 1.0)]));
+
+        // Only the most played move is kept and renormalized.
+        let positions = aggregate(samples.into_iter(), 1, false).expect("move labels");
+        assert_eq!(positions[0].policy, Some(vec![(E4, 1.0)]));
+    }
+
+    #[test]
+    fn aggregate_weights_by_elo() {
+        let samples = [
+            played(Chess::default(), D4, Some(3000)),
+            played(Chess::default(), E4, Some(1000)),
+            // Unrated players count as DEFAULT_ELO.
+            played(Chess::default(), E4, None),
+        ];
+        let positions = aggregate(samples.into_iter(), 2, true).expect("move labels");
+        // The single move of the stronger player outweighs the other two.
+        assert_eq!(positions[0].label, D4);
+        let policy = positions[0].policy.as_ref().expect("policy");
+        assert_eq!(
+            policy.iter().map(|&(index, _)| index).collect::<Vec<_>>(),
+            [D4, E4]
+        );
+        assert!((policy[0].1 - 3000.0 / 5500.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn aggregate_needs_move_labels() {
+        let samples = [Sample::new(Chess::default(), 0.5_f32)];
+        assert!(matches!(
+            aggregate(samples.into_iter(), 2, false),
+            Err(DatasetError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn faster_mates_score_higher() {
+        assert_eq!(Score::Centipawns(-150).pawns(), -1.5);
+        assert_eq!(Score::Mate(3).pawns(), MATE_PAWNS - 3.0);
+        assert!(Score::Mate(1).pawns() > Score::Mate(3).pawns());
+        assert!(Score::Mate(3).pawns() > Score::Centipawns(5000).pawns());
+        // Getting mated later is better.
+        assert!(Score::Mate(-5).pawns() > Score::Mate(-1).pawns());
+        assert!(Score::Mate(-5).pawns() < Score::Centipawns(-5000).pawns());
+    }
+
+    #[test]
+    fn softmax_with_temperature() {
+        let scores = [(D4, Score::Centipawns(0)), (E4, Score::Centipawns(100))];
+        let policy = softmax(&scores, 1.0);
+        assert_eq!(
+            policy.iter().map(|&(index, _)| index).collect::<Vec<_>>(),
+            [E4, D4]
+        );
+        let e = std::f32::consts::E;
+        assert!((policy[0].1 - e / (1.0 + e)).abs() < 1e-6);
+        assert!((policy.iter().map(|(_, p)| p).sum::<f32>() - 1.0).abs() < 1e-6);
+
+        // A lower temperature sharpens the policy, a higher one flattens it.
+        assert!(softmax(&scores, 0.5)[0].1 > policy[0].1);
+        assert!(softmax(&scores, 10.0)[0].1 < policy[0].1);
+        let equal = softmax(&[(D4, Score::Mate(2)), (E4, Score::Mate(2))], 1.0);
+        assert_eq!(
+            equal.iter().map(|&(_, p)| p).collect::<Vec<_>>(),
+            [0.5, 0.5]
+        );
+
+        // Mates do not overflow the exponential.
+        let policy = softmax(&[(D4, Score::Centipawns(0)), (E4, Score::Mate(1))], 0.01);
+        assert_eq!(policy, [(E4, 1.0), (D4, 0.0)]);
+    }
+
+    #[test]
+    fn map_moves_keeps_probabilities() {
+        let policy = vec![(E4, 0.75), (D4, 0.25)];
+        assert_eq!(
+            map_moves(&policy, crate::mirror_output),
+            [(E5, 0.75), (51 * 64 + 35, 0.25)]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet},
+    error::Error,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use clap::ArgMatches;
+use fs_err::File;
+use serde_json::{json, Value};
+use shakmaty::{Board, Role};
+
+use neural_chess::{
+    dataset::{chunk_count, ChunkReader, Label},
+    input_to_board,
+    manifest::Manifest,
+    move_output_count, output_to_eval, output_to_uci, INPUT_LENGTH,
+};
+
+const EVAL_BINS: usize = 20;
+const TOP_MOVES: usize = 20;
+
+/// Distribution reports over all rows of a dataset, collected in a single pass.
+struct Stats {
+    rows: u64,
+    white_to_move: u64,
+    /// The hashes of all inputs so far, if duplicates are counted. Grows with the row count.
+    hashes: Option<HashSet<u64>>,
+    duplicates: u64,
+    material: BTreeMap<u32, u64>,
+    phase: BTreeMap<u32, u64>,
+    piece_count: BTreeMap<u32, u64>,
+    /// One count per move index of the dataset's variant.
+    moves: Vec<u64>,
+    out_of_range_moves: u64,
+    evals: [u64; EVAL_BINS],
+}
+
+impl Stats {
+    fn new(count_duplicates: bool, move_classes: usize) -> Self {
+        Self {
+            rows: 0,
+            white_to_move: 0,
+            hashes: count_duplicates.then(HashSet::new),
+            duplicates: 0,
+            material: BTreeMap::new(),
+            phase: BTreeMap::new(),
+            piece_count: BTreeMap::new(),
+            moves: vec![0; move_classes],
+            out_of_range_moves: 0,
+            evals: [0; EVAL_BINS],
+        }
+    }
+
+    fn add(&mut self, input: &[bool; INPUT_LENGTH], label: Label) {
+        self.rows += 1;
+        if input[0] {
+            self.white_to_move += 1;
+        }
+
+        if let Some(hashes) = &mut self.hashes {
+            let mut hasher = DefaultHasher::new();
+            input.hash(&mut hasher);
+            if !hashes.insert(hasher.finish()) {
+                self.duplicates += 1;
+            }
+        }
+
+        let board = input_to_board(input);
+        *self.material.entry(material(&board)).or_default() += 1;
+        *self.phase.entry(phase(&board)).or_default() += 1;
+        *self
+            .piece_count
+            .entry(board.occupied().count() as u32)
+            .or_default() += 1;
+
+        match label {
+            Label::Move(index) => match self.moves.get_mut(index as usize) {
+                Some(count) => *count += 1,
+                None => self.out_of_range_moves += 1,
+            },
+            Label::Eval(output) => {
+                let bin = (output.clamp(0.0, 1.0) * EVAL_BINS as f32) as usize;
+                self.evals[bin.min(EVAL_BINS - 1)] += 1;
+            }
+        }
+    }
+
+    fn top_moves(&self) -> Vec<(u16, u64)> {
+        let mut moves = self
+            .moves
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(index, &count)| (index as u16, count))
+            .collect::<Vec<_>>();
+        moves.sort_by_key(|&(index, count)| (std::cmp::Reverse(count), index));
+        moves.truncate(TOP_MOVES);
+        moves
+    }
+
+    /// The evaluation range of a bin. The outer bins are open-ended, e.g. `..-2.94`.
+    fn eval_bin_name(bin: usize) -> String {
+        let bound = |bin: usize| format!("{:+.2}", output_to_eval(bin as f32 / EVAL_BINS as f32));
+        let low = if bin == 0 { String::new() } else { bound(bin) };
+        let high = if bin == EVAL_BINS - 1 {
+            String::new()
+        } else {
+            bound(bin + 1)
+        };
+        format!("{low}..{high}")
+    }
+
+    /// The duplicate count, if duplicates are counted.
+    fn duplicates(&self) -> Option<u64> {
+        self.hashes.as_ref().map(|_| self.duplicates)
+    }
+
+    fn print(&self) {
+        println!("Positions: {}", self.rows);
+        if let Some(duplicates) = self.duplicates() {
+            println!("Duplicated positions: {duplicates}");
+        }
+        println!(
+            "Side to move: {} white, {} black ({:.2}% white)",
+            self.white_to_move,
+            self.rows - self.white_to_move,
+            self.white_to_move as f64 * 100.0 / self.rows.max(1) as f64
+        );
+
+        print_histogram("Material", &self.material);
+        print_histogram("Phase (24 = all pieces, 0 = pawns and kings)", &self.phase);
+        print_histogram("Piece count", &self.piece_count);
+
+        if self.moves.iter().any(|&count| count > 0) {
+            println!("\nMost common moves:");
+            for (index, count) in self.top_moves() {
+                println!(
+                    "{:>6}  {count:>10}",
+                    output_to_uci(index).expect("index is in range").to_string()
+                );
+            }
+            if self.out_of_range_moves > 0 {
+                println!("Out of range labels: {}", self.out_of_range_moves);
+            }
+        }
+
+        if self.evals.iter().any(|&count| count > 0) {
+            println!("\nEvaluations:");
+            for (bin, count) in self.evals.iter().enumerate() {
+                println!("{:>14}  {count:>10}", Self::eval_bin_name(bin));
+            }
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "positions": self.rows,
+            "duplicates": self.duplicates(),
+            "white_to_move": self.white_to_move,
+            "black_to_move": self.rows - self.white_to_move,
+            "material": self.material,
+            "phase": self.phase,
+            "piece_count": self.piece_count,
+            "moves": self.moves,
+            "out_of_range_moves": self.out_of_range_moves,
+            "top_moves": self
+                .top_moves()
+                .into_iter()
+                .map(|(index, count)| json!({
+                    "index": index,
+                    "uci": output_to_uci(index).expect("index is in range").to_string(),
+                    "count": count,
+                }))
+                .collect::<Vec<_>>(),
+            "evals": self
+                .evals
+                .iter()
+                .enumerate()
+                .map(|(bin, count)| json!({ "range": Self::eval_bin_name(bin), "count": count }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Writes all reports as `report,key,count` rows.
+    fn write_csv(&self, file: File) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(file);
+        writer.write_record(["report", "key", "count"])?;
+
+        let mut write = |report: &str, key: String, count: u64| {
+            writer.write_record([report, &key, &count.to_string()])
+        };
+
+        write("positions", String::new(), self.rows)?;
+        if let Some(duplicates) = self.duplicates() {
+            write("duplicates", String::new(), duplicates)?;
+        }
+        write("side_to_move", "white".to_owned(), self.white_to_move)?;
+        write(
+            "side_to_move",
+            "black".to_owned(),
+            self.rows - self.white_to_move,
+        )?;
+        for (report, histogram) in [
+            ("material", &self.material),
+            ("phase", &self.phase),
+            ("piece_count", &self.piece_count),
+        ] {
+            for (key, &count) in histogram {
+                write(report, key.to_string(), count)?;
+            }
+        }
+        for (index, &count) in self.moves.iter().enumerate() {
+            if count > 0 {
+                let uci = output_to_uci(index as u16).expect("index is in range");
+                write("moves", uci.to_string(), count)?;
+            }
+        }
+        if self.evals.iter().any(|&count| count > 0) {
+            for (bin, &count) in self.evals.iter().enumerate() {
+                write("evals", Self::eval_bin_name(bin), count)?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn print_histogram(title: &str, histogram: &BTreeMap<u32, u64>) {
+    println!("\n{title}:");
+    for (key, count) in histogram {
+        println!("{key:>6}  {count:>10}");
+    }
+}
+
+/// Material of both sides in pawn units (knight and bishop 3, rook 5, queen 9).
+fn material(board: &Board) -> u32 {
+    board.pawns().count() as u32
+        + (board.knights() | board.bishops()).count() as u32 * 3
+        + board.rooks().count() as u32 * 5
+        + board.queens().count() as u32 * 9
+}
+
+/// Game phase from non-pawn material, from 24 in the starting position down to 0.
+fn phase(board: &Board) -> u32 {
+    let phase = [
+        (Role::Knight, 1),
+        (Role::Bishop, 1),
+        (Role::Rook, 2),
+        (Role::Queen, 4),
+    ]
+    .into_iter()
+    .map(|(role, weight)| board.by_role(role).count() as u32 * weight)
+    .sum::<u32>();
+    phase.min(24)
+}
+
+pub fn main(options: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let prefix = options.get_one::<String>("dataset").expect("required");
+    let dir = Path::new(options.get_one::<String>("dir").expect("default value"));
+
+    let chunks = chunk_count(dir, prefix)?;
+    if chunks == 0 {
+        Err(format!("dataset {prefix} has no files"))?;
+    }
+
+    let variant = Manifest::read(dir, prefix)?.variant;
+    let mut stats = Stats::new(options.get_flag("duplicates"), move_output_count(variant));
+    for chunk_index in 0..chunks {
+        eprint!(
+            "Reading chunk {chunk_index} ({}/{chunks})\r",
+            chunk_index + 1
+        );
+        for row in ChunkReader::open(dir, prefix, chunk_index)? {
+            let (input, label) = row?;
+            stats.add(&input, label);
+        }
+    }
+    eprintln!();
+
+    if let Some(path) = options.get_one::<String>("json") {
+        serde_json::to_writer_pretty(File::create(path)?, &stats.to_json())?;
+    }
+    if let Some(path) = options.get_one::<String>("csv") {
+        stats.write_csv(File::create(path)?)?;
+    }
+    if !options.contains_id("json") && !options.contains_id("csv") {
+        stats.print();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use neural_chess::{chess_to_input, eval_to_output, manifest::Variant, DatasetConfig};
+    use shakmaty::Chess;
+
+    use super::*;
+    use crate::testing::{temp_dir, write_dataset};
+
+    #[test]
+    fn stats_of_a_written_dataset() {
+        let dir = temp_dir("stats");
+        let manifest = write_dataset(&dir, DatasetConfig::default());
+        let mut stats = Stats::new(true, move_output_count(manifest.variant));
+        for chunk_index in 0..chunk_count(&dir, "opera").expect("chunks") {
+            for row in ChunkReader::open(&dir, "opera", chunk_index).expect("chunk") {
+                let (input, label) = row.expect("row");
+                stats.add(&input, label);
+            }
+        }
+        fs_err::remove_dir_all(&dir).expect("temporary directory");
+
+        assert_eq!(stats.rows, 8);
+        assert_eq!(stats.white_to_move, 4);
+        assert_eq!(stats.duplicates(), Some(0));
+        // The last position is the one after 4. dxe5.
+        assert_eq!(stats.material, BTreeMap::from([(77, 1), (78, 7)]));
+        assert_eq!(stats.phase, BTreeMap::from([(24, 8)]));
+        assert_eq!(stats.moves.len(), 64 * 64);
+        assert_eq!(stats.moves.iter().sum::<u64>(), 8);
+        assert_eq!(stats.moves[12 * 64 + 28], 1);
+        assert_eq!(stats.top_moves().len(), 8);
+        assert_eq!(stats.out_of_range_moves, 0);
+        assert_eq!(stats.evals, [0; EVAL_BINS]);
+    }
+
+    #[test]
+    fn duplicates_evals_and_out_of_range_moves() {
+        let input = chess_to_input(&Chess::default());
+        let mut stats = Stats::new(true, move_output_count(Variant::Chess));
+        stats.add(&input, Label::Eval(eval_to_output(0.0)));
+        stats.add(&input, Label::Eval(eval_to_output(100.0)));
+        stats.add(&input, Label::Move(64 * 64));
+        assert_eq!(stats.duplicates(), Some(2));
+        assert_eq!(stats.evals[EVAL_BINS / 2], 1);
+        assert_eq!(stats.evals[EVAL_BINS - 1], 1);
+        assert_eq!(stats.out_of_range_moves, 1);
+        assert_eq!(Stats::eval_bin_name(0).split_once("..").unwrap().0, "");
+        assert!(Stats::eval_bin_name(EVAL_BINS - 1).ends_with(".."));
+
+        // Crazyhouse drops have move indices of their own.
+        let mut stats = Stats::new(false, move_output_count(Variant::Crazyhouse));
+        stats.add(&input, Label::Move(64 * 64));
+        assert_eq!(stats.duplicates(), None);
+        assert_eq!(stats.out_of_range_moves, 0);
+    }
+}
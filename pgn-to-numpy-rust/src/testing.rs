@@ -0,0 +1,50 @@
+//! A small dataset for the tests of the subcommands that read datasets.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use neural_chess::{manifest::Manifest, visitor::MoveVisitor, DatasetConfig, DatasetWriter};
+use pgn_reader::BufferedReader;
+
+/// Morphy's Opera game, whose 33 positions all differ.
+const OPERA_GAME: &str = r#"[Event "Rated Classical game"]
+[WhiteElo "2000"]
+[BlackElo "2000"]
+[TimeControl "600+0"]
+[Result "1-0"]
+
+1. e4 e5 2. Nf3 d6 3. d4 Bg4 4. dxe5 Bxf3 5. Qxf3 dxe5 6. Bc4 Nf6 7. Qb3 Qe7 8. Nc3 c6
+9. Bg5 b5 10. Nxb5 cxb5 11. Bxb5+ Nbd7 12. O-O-O Rd8 13. Rxd7 Rxd7 14. Rd1 Qe6 15. Bxd7+ Nxd7
+16. Qb8+ Nxb8 17. Rd8# 1-0
+"#;
+
+/// An empty directory for the test `name`, which the test removes when it is done.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("pgn-to-numpy-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("temporary directory");
+    dir
+}
+
+/// Writes the first 8 moves of the Opera game as the dataset `opera` in two files of 4 rows.
+pub fn write_dataset(dir: &Path, config: DatasetConfig) -> Manifest {
+    let mut reader = BufferedReader::new_cursor(OPERA_GAME);
+    let samples = reader
+        .read_game(&mut MoveVisitor::new(config.history).with_castling(config.castling))
+        .expect("valid PGN")
+        .flatten()
+        .expect("a decisive game");
+    DatasetWriter::builder("opera")
+        .output_dir(dir)
+        .config(DatasetConfig {
+            total: 8,
+            boards_per_file: 4,
+            ..config
+        })
+        .build()
+        .expect("valid config")
+        .write_moves(samples.into_iter())
+        .expect("dataset written")
+}
@@ -0,0 +1,535 @@
+use std::{collections::BTreeSet, error::Error, io, path::Path, process::exit};
+
+use clap::ArgMatches;
+use fs_err as fs;
+use itertools::Itertools;
+use npyz::NpyFile;
+use shakmaty::{
+    uci::Uci, Bitboard, CastlingMode, Chess, EnPassantMode, FromSetup, Position, PositionError,
+    Rank, Square,
+};
+
+use neural_chess::{
+    castling_output_to_move,
+    dataset::{
+        chunk_extension, dataset_dirs, npz_dir, open_arrays, open_chunk, ArraySource, ChunkReader,
+        Label,
+    },
+    input_to_chess,
+    manifest::{Castling, Encoding, Format, Manifest, Packing, Variant},
+    move_output_count,
+    nnue::{feature_count, NNUE_MAX_FEATURES},
+    output_to_move, output_to_uci, INPUT_LENGTH,
+};
+
+/// Maximum amount of row errors printed per file.
+const MAX_REPORTED_ERRORS: usize = 10;
+
+pub fn main(options: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let prefix = options.get_one::<String>("dataset").expect("required");
+    let dir = Path::new(options.get_one::<String>("dir").expect("default value"));
+    let manifest = Manifest::read(dir, prefix)?;
+    let (input_dir, output_dir) = match manifest.format {
+        Format::Npy => dataset_dirs(dir, prefix),
+        // Both arrays are in the same archive, so they can not be missing individually.
+        Format::Npz => (npz_dir(dir, prefix), npz_dir(dir, prefix)),
+        Format::Tfrecord | Format::Parquet | Format::Arrow => Err(format!(
+            "only npy and npz datasets can be validated, not {:?}",
+            manifest.format
+        ))?,
+    };
+    let extension = chunk_extension(&manifest);
+    if !manifest.has_valid_input_length() {
+        Err(format!(
+            "unsupported input length {} in manifest",
+            manifest.input_length
+        ))?;
+    }
+
+    let input_files = chunk_files(&input_dir, extension)?;
+    let output_files = chunk_files(&output_dir, extension)?;
+
+    let mut failed = false;
+    for file in input_files.symmetric_difference(&output_files) {
+        let (present, missing) = if input_files.contains(file) {
+            (&input_dir, &output_dir)
+        } else {
+            (&output_dir, &input_dir)
+        };
+        println!(
+            "{file}.{extension}: present in {} but missing in {}",
+            present.display(),
+            missing.display()
+        );
+        failed = true;
+    }
+
+    for &chunk_index in input_files.intersection(&output_files) {
+        let errors = validate_chunk(dir, prefix, chunk_index, &manifest)?;
+        if errors.is_empty() {
+            println!("{chunk_index}.{extension}: ok");
+            continue;
+        }
+        failed = true;
+        println!("{chunk_index}.{extension}: {} errors", errors.len());
+        for error in errors.iter().take(MAX_REPORTED_ERRORS) {
+            println!("  {error}");
+        }
+        if errors.len() > MAX_REPORTED_ERRORS {
+            println!("  ...");
+        }
+    }
+
+    if failed {
+        eprintln!("\x1b[1;31mDataset {prefix} is invalid\x1b[0m");
+        exit(1);
+    }
+    eprintln!("\x1b[1;32mDataset {prefix} is valid\x1b[0m");
+
+    Ok(())
+}
+
+/// Collects the chunk indices of all `<index>.<extension>` files in a directory.
+fn chunk_files(dir: &Path, extension: &str) -> io::Result<BTreeSet<usize>> {
+    let mut files = BTreeSet::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let Some(index) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(extension))
+            .and_then(|name| name.strip_suffix('.'))
+            .and_then(|index| index.parse().ok())
+        else {
+            continue;
+        };
+        files.insert(index);
+    }
+    Ok(files)
+}
+
+fn validate_chunk(
+    dir: &Path,
+    prefix: &str,
+    chunk_index: usize,
+    manifest: &Manifest,
+) -> io::Result<Vec<String>> {
+    let mut errors = Vec::new();
+
+    let (inputs, outputs) = open_chunk(dir, prefix, chunk_index, manifest)?;
+    let dtype = |file: &NpyFile<_>| file.dtype().descr().trim_matches('\'').to_owned();
+    let (input_dtype, input_shape) = (dtype(&inputs), inputs.shape().to_vec());
+    let (output_dtype, output_shape) = (dtype(&outputs), outputs.shape().to_vec());
+
+    let expected_dtype = match manifest.packing {
+        _ if manifest.encoding != Encoding::Board => "<i4",
+        Packing::None => "|b1",
+        Packing::Packbits => "|u1",
+        Packing::Sparse => "<i2",
+    };
+    if input_dtype != expected_dtype {
+        errors.push(format!(
+            "input has dtype {input_dtype} instead of {expected_dtype}"
+        ));
+    }
+    let expected_shape = manifest.input_shape();
+    if input_shape.len() < 2 || input_shape[1..] != expected_shape {
+        errors.push(format!(
+            "input has shape {input_shape:?} instead of (N, {})",
+            expected_shape.iter().join(", ")
+        ));
+    }
+    if output_dtype != "<u2" && output_dtype != "<f4" {
+        errors.push(format!(
+            "output has dtype {output_dtype} instead of <u2 or <f4"
+        ));
+    }
+    if output_shape.len() != 1 {
+        errors.push(format!("output has shape {output_shape:?} instead of (N,)"));
+    }
+    if input_shape.first() != output_shape.first() {
+        errors.push(format!(
+            "input has {:?} rows but output has {:?} rows",
+            input_shape.first(),
+            output_shape.first()
+        ));
+    }
+    let mask_length = move_output_count(manifest.variant) / 8;
+    let legal_moves = if manifest.legal_moves {
+        let legal_moves = open_arrays(dir, prefix, chunk_index, manifest, &["legal"])?.remove(0);
+        let expected_shape = [input_shape[0], mask_length as u64];
+        if dtype(&legal_moves) != "|u1" || legal_moves.shape() != expected_shape {
+            errors.push(format!(
+                "legal move masks have dtype {} and shape {:?} instead of |u1 and {expected_shape:?}",
+                dtype(&legal_moves),
+                legal_moves.shape()
+            ));
+        }
+        legal_moves.into_vec::<u8>().ok()
+    } else {
+        None
+    };
+    if let Some(history_shape) = manifest.history_shape() {
+        let history = open_arrays(dir, prefix, chunk_index, manifest, &["history"])?.remove(0);
+        let expected_shape = [&input_shape[..1], &history_shape].concat();
+        if dtype(&history) != "|b1" || history.shape() != expected_shape {
+            errors.push(format!(
+                "history planes have dtype {} and shape {:?} instead of |b1 and {expected_shape:?}",
+                dtype(&history),
+                history.shape()
+            ));
+        }
+    }
+    if let Some(variant_inputs) = &manifest.variant_inputs {
+        let variant = open_arrays(dir, prefix, chunk_index, manifest, &["variant"])?.remove(0);
+        let expected_shape = [input_shape[0], variant_inputs.len() as u64];
+        if dtype(&variant) != "|u1" || variant.shape() != expected_shape {
+            errors.push(format!(
+                "variant inputs have dtype {} and shape {:?} instead of |u1 and {expected_shape:?}",
+                dtype(&variant),
+                variant.shape()
+            ));
+        }
+    }
+    let policy = if let Some(top_k) = manifest.policy_top_k {
+        let mut arrays = open_arrays(
+            dir,
+            prefix,
+            chunk_index,
+            manifest,
+            &["policy_moves", "policy_probs"],
+        )?;
+        let (probabilities, moves) = (arrays.pop().expect("two arrays"), arrays.remove(0));
+        let expected_shape = [input_shape[0], top_k as u64];
+        if dtype(&moves) != "<u2" || moves.shape() != expected_shape {
+            errors.push(format!(
+                "policy moves have dtype {} and shape {:?} instead of <u2 and {expected_shape:?}",
+                dtype(&moves),
+                moves.shape()
+            ));
+        }
+        if dtype(&probabilities) != "<f4" || probabilities.shape() != expected_shape {
+            errors.push(format!(
+                "policy probabilities have dtype {} and shape {:?} instead of <f4 and {expected_shape:?}",
+                dtype(&probabilities),
+                probabilities.shape()
+            ));
+        }
+        moves
+            .into_vec::<u16>()
+            .ok()
+            .zip(probabilities.into_vec::<f32>().ok())
+            .map(|policy| (policy, top_k))
+    } else {
+        None
+    };
+    if !errors.is_empty() {
+        return Ok(errors);
+    }
+    if manifest.encoding != Encoding::Board {
+        return validate_nnue_chunk(dir, prefix, chunk_index, manifest, inputs);
+    }
+
+    for (row, result) in ChunkReader::new(inputs, outputs, manifest)?.enumerate() {
+        let (input, label) = match result {
+            Ok(row) => row,
+            // A corrupt row, the following rows can still be read.
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                errors.push(format!("row {row}: {err}"));
+                continue;
+            }
+            Err(err) => {
+                errors.push(format!("row {row}: {err}"));
+                break;
+            }
+        };
+        if let Err(err) = validate_row(&input, label, manifest.castling, manifest.variant) {
+            errors.push(format!("row {row}: {err}"));
+        }
+        if let (Some(legal_moves), Label::Move(output)) = (&legal_moves, label) {
+            let mask = &legal_moves[row * mask_length..(row + 1) * mask_length];
+            let output = output as usize;
+            if output >= move_output_count(manifest.variant) {
+                errors.push(format!(
+                    "row {row}: move index {output} is outside the legal move mask"
+                ));
+            } else if mask[output / 8] & (1 << (7 - output % 8)) == 0 {
+                errors.push(format!("row {row}: label is not in the legal move mask"));
+            }
+        }
+        if let Some(((moves, probabilities), top_k)) = &policy {
+            let range = row * top_k..(row + 1) * top_k;
+            let sum: f32 = probabilities[range.clone()].iter().sum();
+            if (sum - 1.0).abs() > 1e-3 {
+                errors.push(format!("row {row}: policy probabilities sum to {sum}"));
+            }
+            if matches!(label, Label::Move(_)) && label != Label::Move(moves[range.start]) {
+                errors.push(format!(
+                    "row {row}: label is not the most likely policy move"
+                ));
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// NNUE inputs can not be decoded to positions, so only the feature indices and the side to
+/// move are checked.
+fn validate_nnue_chunk(
+    dir: &Path,
+    prefix: &str,
+    chunk_index: usize,
+    manifest: &Manifest,
+    inputs: NpyFile<ArraySource>,
+) -> io::Result<Vec<String>> {
+    let mut errors = Vec::new();
+    let rows = inputs.shape()[0];
+    let turn = open_arrays(dir, prefix, chunk_index, manifest, &["turn"])?.remove(0);
+    if turn.dtype().descr().trim_matches('\'') != "|b1" || turn.shape() != [rows] {
+        errors.push(format!(
+            "turn has dtype {} and shape {:?} instead of |b1 and [{rows}]",
+            turn.dtype().descr(),
+            turn.shape()
+        ));
+    }
+
+    let count = feature_count(manifest.encoding) as i32;
+    let inputs = inputs
+        .into_vec::<i32>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    for (row, features) in inputs.chunks(2 * NNUE_MAX_FEATURES).enumerate() {
+        for (perspective, features) in ["white", "black"]
+            .iter()
+            .zip(features.chunks(NNUE_MAX_FEATURES))
+        {
+            if let Some(index) = features.iter().find(|&&index| index < -1 || index >= count) {
+                errors.push(format!(
+                    "row {row}: {perspective} feature index {index} out of range"
+                ));
+            }
+            if !features
+                .iter()
+                .skip_while(|&&index| index != -1)
+                .all(|&index| index == -1)
+            {
+                errors.push(format!(
+                    "row {row}: {perspective} features are not padded at the end"
+                ));
+            }
+        }
+    }
+    Ok(errors)
+}
+
+/// Positions of other variants than chess can not be decoded from the board alone, so only their
+/// encoding and the range of their labels are checked.
+fn validate_row(
+    input: &[bool; INPUT_LENGTH],
+    label: Label,
+    castling: Castling,
+    variant: Variant,
+) -> Result<(), String> {
+    for (index, square) in Square::ALL.into_iter().enumerate() {
+        let block = &input[index * (1 + 2 * 6) + 1..(index + 1) * (1 + 2 * 6) + 1];
+        let set = block.iter().filter(|&&value| value).count();
+        if set != 1 {
+            return Err(format!(
+                "square {square} has {set} set values instead of one"
+            ));
+        }
+    }
+
+    if variant != Variant::Chess {
+        return match label {
+            Label::Move(output) if usize::from(output) >= move_output_count(variant) => {
+                Err(format!("move index {output} out of range"))
+            }
+            Label::Eval(output) if !(0.0..=1.0).contains(&output) => {
+                Err(format!("evaluation output {output} out of range"))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    let chess = input_to_chess(input).map_err(|err| format!("illegal position: {err}"))?;
+
+    match label {
+        Label::Move(output) => {
+            let uci = output_to_uci(output).ok_or(format!("move index {output} out of range"))?;
+            if castling_output_to_move(&chess, output, castling).is_none()
+                && !is_en_passant(&chess, output)
+                && !is_chess960_castle(&chess, output, castling)
+            {
+                return Err(format!("{uci} is not a legal move"));
+            }
+        }
+        Label::Eval(output) => {
+            if !(0.0..=1.0).contains(&output) {
+                return Err(format!("evaluation output {output} out of range"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// En passant squares are not part of the encoding, so a diagonal pawn move to an empty square
+/// is legal if it is legal with the corresponding en passant square.
+fn is_en_passant(chess: &Chess, output: u16) -> bool {
+    let Some(Uci::Normal { from, to, .. }) = output_to_uci(output) else {
+        return false;
+    };
+    let turn = chess.turn();
+    if chess.board().piece_at(from) != Some(turn.pawn())
+        || chess.board().piece_at(to).is_some()
+        || to.rank() != turn.relative_rank(Rank::Sixth)
+    {
+        return false;
+    }
+
+    let mut setup = chess.clone().into_setup(EnPassantMode::Always);
+    setup.ep_square = Some(to);
+    let Ok(chess) = Chess::from_setup(setup, CastlingMode::Standard) else {
+        return false;
+    };
+    output_to_move(&chess, output).is_some_and(|m| m.is_en_passant())
+}
+
+/// [`input_to_chess`] only assumes castling rights for the standard starting squares, so a
+/// castling label of a Chess960 position is legal if it is legal with a castling right for every
+/// rook on the back rank of the side to move.
+fn is_chess960_castle(chess: &Chess, output: u16, castling: Castling) -> bool {
+    let turn = chess.turn();
+    let backrank = Bitboard::from_rank(turn.backrank());
+    if (chess.board().kings() & chess.us() & backrank).is_empty() {
+        return false;
+    }
+
+    let mut setup = chess.clone().into_setup(EnPassantMode::Legal);
+    setup.castling_rights = chess.board().rooks() & chess.us() & backrank;
+    let Ok(chess) = Chess::from_setup(setup, CastlingMode::Chess960)
+        .or_else(PositionError::ignore_invalid_castling_rights)
+    else {
+        return false;
+    };
+    castling_output_to_move(&chess, output, castling).is_some_and(|m| m.is_castle())
+}
+
+#[cfg(test)]
+mod tests {
+    use neural_chess::{chess_to_input, DatasetConfig};
+    use npyz::WriterBuilder;
+
+    use super::*;
+    use crate::testing::{temp_dir, write_dataset};
+
+    #[test]
+    fn written_dataset_is_valid_until_labels_are_corrupted() {
+        let dir = temp_dir("validate");
+        let manifest = write_dataset(
+            &dir,
+            DatasetConfig {
+                legal_moves: true,
+                ..DatasetConfig::default()
+            },
+        );
+        let (input_dir, output_dir) = dataset_dirs(&dir, "opera");
+        assert_eq!(
+            chunk_files(&input_dir, "npy").unwrap(),
+            BTreeSet::from([0, 1])
+        );
+        for chunk_index in 0..2 {
+            let errors = validate_chunk(&dir, "opera", chunk_index, &manifest).expect("chunk");
+            assert_eq!(errors, Vec::<String>::new());
+        }
+
+        // 3. d4 e2e4 4. 9999 Bxf3: e2 is empty and 9999 is no move index.
+        let mut outputs = npyz::WriteOptions::new()
+            .default_dtype()
+            .shape(&[4])
+            .writer(fs::File::create(output_dir.join("1.npy")).expect("output file"))
+            .begin_nd()
+            .expect("npy header");
+        let labels: [u16; 4] = [11 * 64 + 27, 12 * 64 + 28, 9999, 30 * 64 + 21];
+        outputs.extend(labels).expect("labels");
+        outputs.finish().expect("labels");
+        let errors = validate_chunk(&dir, "opera", 1, &manifest).expect("chunk");
+        fs::remove_dir_all(&dir).expect("temporary directory");
+
+        assert_eq!(
+            errors,
+            [
+                "row 1: e2e4 is not a legal move",
+                "row 1: label is not in the legal move mask",
+                "row 2: move index 9999 out of range",
+                "row 2: move index 9999 is outside the legal move mask",
+            ]
+        );
+    }
+
+    #[test]
+    fn rows() {
+        let chess = Chess::default();
+        let input = chess_to_input(&chess);
+        let validate = |input: &[bool; INPUT_LENGTH], label| {
+            validate_row(input, label, Castling::KingDestination, Variant::Chess)
+        };
+        assert_eq!(validate(&input, Label::Move(12 * 64 + 28)), Ok(()));
+        assert_eq!(validate(&input, Label::Eval(0.5)), Ok(()));
+        assert_eq!(
+            validate(&input, Label::Eval(1.5)),
+            Err("evaluation output 1.5 out of range".to_owned())
+        );
+
+        // A white pawn on a1 next to the rook.
+        let mut two_pieces = input;
+        two_pieces[2] = true;
+        assert_eq!(
+            validate(&two_pieces, Label::Move(12 * 64 + 28)),
+            Err("square a1 has 2 set values instead of one".to_owned())
+        );
+
+        // Other variants only check the range of the label.
+        assert_eq!(
+            validate_row(
+                &input,
+                Label::Move(64 * 64),
+                Castling::KingDestination,
+                Variant::Crazyhouse
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn en_passant_and_chess960_castling_labels() {
+        let position = |fen: &str| -> Chess {
+            let fen: shakmaty::fen::Fen = fen.parse().expect("valid FEN");
+            fen.into_position(CastlingMode::Chess960).expect("legal")
+        };
+        // The encoding drops the en passant square of exf6.
+        let chess = position("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3");
+        let chess = input_to_chess(&chess_to_input(&chess)).expect("legal");
+        assert!(is_en_passant(&chess, 36 * 64 + 45));
+        assert!(!is_en_passant(&chess, 36 * 64 + 44));
+
+        // The rook on b1 is not on a standard castling square.
+        let chess = position("1r2k3/8/8/8/8/8/8/1R2K3 w Bb - 0 1");
+        let chess = input_to_chess(&chess_to_input(&chess)).expect("legal");
+        assert!(is_chess960_castle(
+            &chess,
+            4 * 64 + 2,
+            Castling::KingDestination
+        ));
+        assert!(is_chess960_castle(
+            &chess,
+            4 * 64 + 1,
+            Castling::KingTakesRook
+        ));
+        assert!(!is_chess960_castle(
+            &chess,
+            4 * 64 + 6,
+            Castling::KingDestination
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use shakmaty::{variant::VariantPosition, Color, Position, Role};
+
+use crate::manifest::Variant;
+
+/// The pocket counts written for Crazyhouse positions, in order.
+pub const CRAZYHOUSE_INPUTS: [&str; 10] = [
+    "white-pawn-pocket",
+    "white-knight-pocket",
+    "white-bishop-pocket",
+    "white-rook-pocket",
+    "white-queen-pocket",
+    "black-pawn-pocket",
+    "black-knight-pocket",
+    "black-bishop-pocket",
+    "black-rook-pocket",
+    "black-queen-pocket",
+];
+
+/// The remaining checks written for Three-check positions, in order.
+pub const THREE_CHECK_INPUTS: [&str; 2] = ["white-remaining-checks", "black-remaining-checks"];
+
+/// The roles that can be dropped in Crazyhouse, in the order of their move indices.
+pub const DROP_ROLES: [Role; 5] = [
+    Role::Pawn,
+    Role::Knight,
+    Role::Bishop,
+    Role::Rook,
+    Role::Queen,
+];
+
+impl From<Variant> for shakmaty::variant::Variant {
+    fn from(variant: Variant) -> Self {
+        match variant {
+            Variant::Chess => Self::Chess,
+            Variant::Atomic => Self::Atomic,
+            Variant::Antichess => Self::Antichess,
+            Variant::KingOfTheHill => Self::KingOfTheHill,
+            Variant::ThreeCheck => Self::ThreeCheck,
+            Variant::Crazyhouse => Self::Crazyhouse,
+            Variant::RacingKings => Self::RacingKings,
+            Variant::Horde => Self::Horde,
+        }
+    }
+}
+
+impl From<shakmaty::variant::Variant> for Variant {
+    fn from(variant: shakmaty::variant::Variant) -> Self {
+        use shakmaty::variant::Variant as V;
+        match variant {
+            V::Chess => Self::Chess,
+            V::Atomic => Self::Atomic,
+            V::Antichess => Self::Antichess,
+            V::KingOfTheHill => Self::KingOfTheHill,
+            V::ThreeCheck => Self::ThreeCheck,
+            V::Crazyhouse => Self::Crazyhouse,
+            V::RacingKings => Self::RacingKings,
+            V::Horde => Self::Horde,
+        }
+    }
+}
+
+/// The names of the inputs a variant needs beyond the board, empty for most variants.
+pub fn variant_inputs(variant: Variant) -> &'static [&'static str] {
+    match variant {
+        Variant::Crazyhouse => &CRAZYHOUSE_INPUTS,
+        Variant::ThreeCheck => &THREE_CHECK_INPUTS,
+        _ => &[],
+    }
+}
+
+/// The values of the [`variant_inputs`] of a position: the pocket counts of Crazyhouse and
+/// the remaining checks of Three-check.
+pub fn position_to_variant_inputs(position: &VariantPosition) -> Vec<u8> {
+    let mut values = Vec::new();
+    if let Some(pockets) = position.pockets() {
+        for color in Color::ALL {
+            values.extend(DROP_ROLES.map(|role| *pockets.get(color).get(role)));
+        }
+    }
+    if let Some(remaining_checks) = position.remaining_checks() {
+        values.extend(Color::ALL.map(|color| u8::from(*remaining_checks.get(color))));
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Move, Square};
+
+    use super::*;
+    use crate::{
+        legal_move_mask, manifest::Castling, move_output_count, move_to_output, output_to_move,
+        output_to_uci, MOVE_OUTPUTS,
+    };
+
+    const POCKETS: &str = "rnb1kb1r/pppppppp/8/8/8/8/PPPPPPP1/RNBQKBNR[NQp] w KQkq - 0 1";
+
+    fn position(variant: Variant, fen: &str) -> VariantPosition {
+        let fen: Fen = fen.parse().expect("valid FEN");
+        VariantPosition::from_setup(variant.into(), fen.into_setup(), CastlingMode::Standard)
+            .expect("legal position")
+    }
+
+    #[test]
+    fn crazyhouse_drop_indices() {
+        // White has a knight and a queen in hand, Black a pawn.
+        let chess = position(Variant::Crazyhouse, POCKETS);
+        let knight_drop = Move::Put {
+            role: Role::Knight,
+            to: Square::E4,
+        };
+        let index = MOVE_OUTPUTS as u16 + 64 + Square::E4 as u16;
+        assert_eq!(move_to_output(&knight_drop), index);
+        assert_eq!(
+            output_to_uci(index),
+            Some(Uci::Put {
+                role: Role::Knight,
+                to: Square::E4
+            })
+        );
+        assert_eq!(output_to_move(&chess, index), Some(knight_drop));
+        // White has no rook to drop.
+        let rook_drop = MOVE_OUTPUTS as u16 + 3 * 64 + Square::E4 as u16;
+        assert_eq!(
+            output_to_uci(rook_drop),
+            Some(Uci::Put {
+                role: Role::Rook,
+                to: Square::E4
+            })
+        );
+        assert_eq!(output_to_move(&chess, rook_drop), None);
+        // Kings can not be dropped, so there are no indices past the queen drops.
+        assert_eq!(
+            move_output_count(Variant::Crazyhouse),
+            MOVE_OUTPUTS + 5 * 64
+        );
+        assert_eq!(output_to_uci(MOVE_OUTPUTS as u16 + 5 * 64), None);
+
+        for m in chess.legal_moves() {
+            assert_eq!(output_to_move(&chess, move_to_output(&m)), Some(m));
+        }
+        let mask = legal_move_mask(&chess, Castling::KingDestination);
+        assert_eq!(mask.len(), move_output_count(Variant::Crazyhouse) / 8);
+        let bits = mask.iter().map(|byte| byte.count_ones()).sum::<u32>();
+        assert_eq!(bits as usize, chess.legal_moves().len());
+    }
+
+    #[test]
+    fn pockets_and_remaining_checks() {
+        let chess = position(Variant::Crazyhouse, POCKETS);
+        assert_eq!(
+            position_to_variant_inputs(&chess),
+            [0, 1, 0, 0, 1, 1, 0, 0, 0, 0]
+        );
+        assert_eq!(variant_inputs(Variant::Crazyhouse).len(), 10);
+
+        let chess = position(
+            Variant::ThreeCheck,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 2+3 0 1",
+        );
+        assert_eq!(position_to_variant_inputs(&chess), [2, 3]);
+        assert!(position_to_variant_inputs(&position(
+            Variant::Atomic,
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        ))
+        .is_empty());
+    }
+}
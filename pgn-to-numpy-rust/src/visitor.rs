@@ -0,0 +1,546 @@
+use std::{mem, sync::Arc};
+
+use itertools::Itertools;
+use nom::{branch::alt, bytes::complete::tag, combinator::opt, number::complete::float};
+use pgn_reader::{RawComment, RawHeader, SanPlus, Skip, Visitor};
+use shakmaty::{fen::Fen, variant::VariantPosition, Board, CastlingMode, Move, Outcome, Position};
+
+use crate::{
+    history::{GameHistory, History},
+    manifest::{Castling, Variant},
+    GameInfo, Sample,
+};
+
+const MIN_ELO: u32 = 1500;
+const MIN_TIME: u32 = 300;
+
+const ONLY_CHECKMATES: bool = true;
+const ONLY_OPENINGS: bool = false;
+const ONLY_MIDDLE_GAME: bool = false;
+const ONLY_ENDGAME: bool = false;
+
+/// The position a game starts from, given by its `[FEN]`, `[SetUp]` and `[Variant]` headers.
+#[derive(Debug, Clone, Default)]
+struct StartingPosition {
+    fen: Option<Fen>,
+    /// `[SetUp "0"]` tells to ignore the FEN, `[SetUp "1"]` requires one.
+    set_up: Option<bool>,
+    chess960: bool,
+    variant: Variant,
+    /// Set by unparsable FENs and unknown variants.
+    unsupported: bool,
+}
+
+impl StartingPosition {
+    fn header(&mut self, key: &[u8], value: &str) {
+        match key {
+            b"FEN" => match value.parse() {
+                Ok(fen) => self.fen = Some(fen),
+                Err(_) => self.unsupported = true,
+            },
+            b"SetUp" => self.set_up = Some(value != "0"),
+            b"Variant" => match value.to_ascii_lowercase().as_str() {
+                "standard" | "from position" => {}
+                "chess960" | "chess 960" | "fischerandom" => self.chess960 = true,
+                "atomic" => self.variant = Variant::Atomic,
+                "antichess" | "giveaway" => self.variant = Variant::Antichess,
+                "king of the hill" | "kingofthehill" => self.variant = Variant::KingOfTheHill,
+                "three-check" | "threecheck" | "3check" => self.variant = Variant::ThreeCheck,
+                "crazyhouse" => self.variant = Variant::Crazyhouse,
+                "racing kings" | "racingkings" => self.variant = Variant::RacingKings,
+                "horde" => self.variant = Variant::Horde,
+                _ => self.unsupported = true,
+            },
+            _ => {}
+        }
+    }
+
+    /// The position to replay the moves from, if the game is of the given variant and can be
+    /// replayed at all.
+    fn position(&self, variant: Variant) -> Option<VariantPosition> {
+        if self.unsupported || self.variant != variant {
+            return None;
+        }
+        let initial = VariantPosition::new(variant.into());
+        match (&self.fen, self.set_up) {
+            (_, Some(false)) => Some(initial),
+            (None, Some(true)) => None,
+            (Some(fen), _) => VariantPosition::from_setup(
+                variant.into(),
+                fen.as_setup().clone(),
+                CastlingMode::from_chess960(self.chess960),
+            )
+            .ok(),
+            (None, None) => Some(initial),
+        }
+    }
+}
+
+/// Collects the positions and played moves of decisive, rated games.
+///
+/// Games start from their `[FEN]` header, if any. Games with illegal moves, unparsable headers
+/// or of another variant than the one of the visitor (standard chess and Chess960 by default)
+/// are skipped.
+#[derive(Debug, Clone)]
+pub struct MoveVisitor {
+    board: VariantPosition,
+    variant: Variant,
+    starting_position: StartingPosition,
+    moves: Vec<(VariantPosition, Move, Option<History>)>,
+    considerable_game: bool,
+    move_count: usize,
+    game: GameInfo,
+    history: GameHistory,
+    /// The number of earlier boards to keep per position, if the history is written at all.
+    history_length: Option<usize>,
+}
+
+impl MoveVisitor {
+    /// Creates a visitor that records `history_length` earlier boards per position, if any.
+    pub fn new(history_length: Option<usize>) -> Self {
+        Self {
+            board: VariantPosition::new(Variant::Chess.into()),
+            variant: Variant::Chess,
+            starting_position: StartingPosition::default(),
+            moves: Vec::new(),
+            move_count: 0,
+            considerable_game: true,
+            game: GameInfo::default(),
+            history: GameHistory::default(),
+            history_length,
+        }
+    }
+
+    /// Only collects the games of the given variant.
+    pub fn with_variant(self, variant: Variant) -> Self {
+        Self { variant, ..self }
+    }
+
+    /// Indexes the last move of the history planes like the labels of `castling`.
+    pub fn with_castling(self, castling: Castling) -> Self {
+        Self {
+            history: GameHistory::new(castling),
+            ..self
+        }
+    }
+}
+
+impl Visitor for MoveVisitor {
+    type Result = Option<Vec<Sample<Move>>>;
+
+    fn begin_game(&mut self) {
+        self.board = VariantPosition::new(self.variant.into());
+        self.starting_position = StartingPosition::default();
+        self.moves.clear();
+        self.move_count = 0;
+        self.considerable_game = true;
+        self.game = GameInfo::default();
+        self.history.clear();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        let value = value.decode_utf8_lossy();
+        if value == "-" || value == "?" {
+            return;
+        }
+        self.game.header(key, &value);
+        self.starting_position.header(key, &value);
+        if key == b"TimeControl" {
+            let Some((time, inc)) = value
+                .split('+')
+                .map(|s| s.parse::<u32>().ok())
+                .collect_tuple()
+                .and_then(|(time, inc)| time.zip(inc))
+            else {
+                self.considerable_game = false;
+                return;
+            };
+            let heuristic = time + inc * 30;
+            if heuristic < MIN_TIME {
+                self.considerable_game = false;
+            }
+        } else if key.ends_with(b"Elo") {
+            if value.parse::<u32>().map_or(true, |elo| elo < MIN_ELO) {
+                self.considerable_game = false;
+            }
+        } else if key == b"Result" && ONLY_CHECKMATES && value != "1-0" && value != "0-1" {
+            self.considerable_game = false;
+        }
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        match self.starting_position.position(self.variant) {
+            Some(chess) => {
+                // Count plies from the start of the game, so that the filters by game phase
+                // also work for games from a position.
+                self.move_count = 2 * (chess.fullmoves().get() as usize - 1)
+                    + usize::from(chess.turn().is_black());
+                self.board = chess;
+            }
+            None => self.considerable_game = false,
+        }
+        Skip(!self.considerable_game)
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        if !self.considerable_game {
+            return;
+        }
+        if ONLY_OPENINGS && self.move_count >= 15 {
+            return;
+        }
+
+        self.move_count += 1;
+        match san_plus.san.to_move(&self.board) {
+            Ok(m) => {
+                if (!ONLY_MIDDLE_GAME
+                    || (self.move_count < 15 || material_count(self.board.board()) < 28))
+                    && (!ONLY_ENDGAME || material_count(self.board.board()) >= 28)
+                {
+                    let history = self
+                        .history_length
+                        .map(|length| self.history.history(&self.board, length));
+                    self.moves.push((self.board.clone(), m.clone(), history));
+                }
+                self.history.push(&self.board, &m);
+                self.board.play_unchecked(&m);
+            }
+            Err(_) => self.considerable_game = false,
+        }
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        if !self.considerable_game {
+            return None;
+        }
+        // Variants end by their own rules, e.g. by exploding the king in Atomic.
+        if ONLY_CHECKMATES && !matches!(self.board.outcome(), Some(Outcome::Decisive { .. })) {
+            return None;
+        }
+        let game = Arc::new(mem::take(&mut self.game));
+        Some(
+            mem::take(&mut self.moves)
+                .into_iter()
+                .map(|(chess, m, history)| Sample {
+                    chess,
+                    label: m,
+                    game: Some(game.clone()),
+                    policy: None,
+                    history,
+                })
+                .collect(),
+        )
+    }
+}
+
+fn material_count(board: &Board) -> usize {
+    (board.knights() & board.bishops()).count() * 3
+        + board.queens().count() * 9
+        + board.rooks().count() * 5
+        + board.pawns().count()
+}
+
+/// Collects the positions of games with `[%eval ...]` comments together with their evaluation
+/// in pawns, from White's point of view.
+///
+/// Games start from their `[FEN]` header, if any. They stop contributing positions at the first
+/// comment without an evaluation, and are dropped entirely if they contain an illegal move or
+/// are of another variant than the one of the visitor.
+pub struct EvalVisitor {
+    board: VariantPosition,
+    variant: Variant,
+    starting_position: StartingPosition,
+    evaluations: Vec<(VariantPosition, f32, Option<History>)>,
+    has_evaluations: bool,
+    game: GameInfo,
+    history: GameHistory,
+    /// The number of earlier boards to keep per position, if the history is written at all.
+    history_length: Option<usize>,
+}
+
+impl Default for EvalVisitor {
+    fn default() -> Self {
+        Self {
+            board: VariantPosition::new(Variant::Chess.into()),
+            variant: Variant::Chess,
+            starting_position: StartingPosition::default(),
+            evaluations: Vec::default(),
+            has_evaluations: true,
+            game: GameInfo::default(),
+            history: GameHistory::default(),
+            history_length: None,
+        }
+    }
+}
+
+impl EvalVisitor {
+    /// Creates a visitor that records `history_length` earlier boards per position, if any.
+    pub fn new(history_length: Option<usize>) -> Self {
+        Self {
+            history_length,
+            ..Self::default()
+        }
+    }
+
+    /// Only collects the games of the given variant.
+    pub fn with_variant(self, variant: Variant) -> Self {
+        Self { variant, ..self }
+    }
+
+    /// Indexes the last move of the history planes like the labels of `castling`.
+    pub fn with_castling(self, castling: Castling) -> Self {
+        Self {
+            history: GameHistory::new(castling),
+            ..self
+        }
+    }
+}
+
+impl Visitor for EvalVisitor {
+    type Result = Vec<Sample<f32>>;
+
+    fn begin_game(&mut self) {
+        self.board = VariantPosition::new(self.variant.into());
+        self.starting_position = StartingPosition::default();
+        self.evaluations.clear();
+        self.has_evaluations = true;
+        self.game = GameInfo::default();
+        self.history.clear();
+    }
+
+    fn header(&mut self, key: &[u8], value: RawHeader<'_>) {
+        let value = value.decode_utf8_lossy();
+        self.game.header(key, &value);
+        self.starting_position.header(key, &value);
+    }
+
+    fn end_headers(&mut self) -> Skip {
+        match self.starting_position.position(self.variant) {
+            Some(chess) => self.board = chess,
+            None => self.has_evaluations = false,
+        }
+        Skip(!self.has_evaluations)
+    }
+
+    fn san(&mut self, san_plus: SanPlus) {
+        if !self.has_evaluations {
+            return;
+        }
+        let Ok(m) = san_plus.san.to_move(&self.board) else {
+            self.has_evaluations = false;
+            self.evaluations.clear();
+            return;
+        };
+        self.history.push(&self.board, &m);
+        self.board.play_unchecked(&m);
+    }
+
+    fn begin_variation(&mut self) -> Skip {
+        Skip(true)
+    }
+
+    fn comment(&mut self, comment: RawComment<'_>) {
+        if !self.has_evaluations {
+            return;
+        }
+        // The comment is in the form "[%eval -0.01] [%clk 0:00:30]". We want to extract the eval
+        // as a float.
+        let comment = comment.as_bytes();
+        let Some(first_bracket) = comment.iter().position(|&b| b == b'[') else {
+            self.has_evaluations = false;
+            return;
+        };
+
+        let Ok((_, eval)) = parse_eval_comment(&comment[first_bracket..]) else {
+            self.has_evaluations = false;
+            return;
+        };
+        let history = self
+            .history_length
+            .map(|length| self.history.history(&self.board, length));
+        self.evaluations.push((self.board.clone(), eval, history));
+    }
+
+    fn end_game(&mut self) -> Self::Result {
+        let game = Arc::new(mem::take(&mut self.game));
+        mem::take(&mut self.evaluations)
+            .into_iter()
+            .map(|(chess, eval, history)| Sample {
+                chess,
+                label: eval,
+                game: Some(game.clone()),
+                policy: None,
+                history,
+            })
+            .collect()
+    }
+}
+
+fn parse_eval_comment(input: &[u8]) -> nom::IResult<&[u8], f32> {
+    let (input, _) = tag(b"[%eval ")(input)?;
+    alt((float, parse_checkmate))(input)
+}
+
+fn parse_checkmate(input: &[u8]) -> nom::IResult<&[u8], f32> {
+    let (input, _) = tag(b"#")(input)?;
+    // If the next character is a minus, return -inf.
+    // If it is a number (not a plus), return inf.
+    let (input, sign) = opt(tag(b"-"))(input)?;
+
+    if sign.is_some() {
+        Ok((input, f32::NEG_INFINITY))
+    } else {
+        Ok((input, f32::INFINITY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pgn_reader::BufferedReader;
+    use shakmaty::{fen::Fen, Bitboard, EnPassantMode, Square};
+
+    use super::*;
+
+    /// The FEN of the position a game with the given headers starts from, if any.
+    fn starting_fen(headers: &[(&str, &str)]) -> Option<String> {
+        let mut starting_position = StartingPosition::default();
+        for (key, value) in headers {
+            starting_position.header(key.as_bytes(), value);
+        }
+        let chess = starting_position.position(Variant::Chess)?;
+        Some(Fen::from_position(chess, EnPassantMode::Legal).to_string())
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const AFTER_E4: &str = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+
+    #[test]
+    fn fen_and_set_up_headers() {
+        assert_eq!(starting_fen(&[]).as_deref(), Some(START));
+        assert_eq!(
+            starting_fen(&[("FEN", AFTER_E4)]).as_deref(),
+            Some(AFTER_E4)
+        );
+        assert_eq!(
+            starting_fen(&[("SetUp", "1"), ("FEN", AFTER_E4)]).as_deref(),
+            Some(AFTER_E4)
+        );
+        // [SetUp "0"] ignores the FEN, [SetUp "1"] requires one.
+        assert_eq!(
+            starting_fen(&[("SetUp", "0"), ("FEN", AFTER_E4)]).as_deref(),
+            Some(START)
+        );
+        assert_eq!(starting_fen(&[("SetUp", "1")]), None);
+        // Unparsable and illegal positions skip the game.
+        assert_eq!(starting_fen(&[("FEN", "not a FEN")]), None);
+        assert_eq!(starting_fen(&[("FEN", "8/8/8/8/8/8/8/8 w - - 0 1")]), None);
+    }
+
+    #[test]
+    fn chess960_fens_keep_their_castling_rights() {
+        // The rooks on e1 and g1 are not on the standard castling squares.
+        let mut starting_position = StartingPosition::default();
+        starting_position.header(b"Variant", "Chess960");
+        starting_position.header(
+            b"FEN",
+            "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w GEge - 0 1",
+        );
+        let chess = starting_position
+            .position(Variant::Chess)
+            .expect("legal position");
+        assert_eq!(chess.castles().mode(), CastlingMode::Chess960);
+        assert_eq!(
+            chess.castles().castling_rights(),
+            Bitboard::from_iter([Square::E1, Square::G1, Square::E8, Square::G8])
+        );
+    }
+
+    #[test]
+    fn variant_headers() {
+        let variant_of = |name: &str| {
+            let mut starting_position = StartingPosition::default();
+            starting_position.header(b"Variant", name);
+            [
+                Variant::Chess,
+                Variant::Atomic,
+                Variant::Antichess,
+                Variant::KingOfTheHill,
+                Variant::ThreeCheck,
+                Variant::Crazyhouse,
+                Variant::RacingKings,
+                Variant::Horde,
+            ]
+            .into_iter()
+            .find(|&variant| starting_position.position(variant).is_some())
+        };
+        assert_eq!(variant_of("Standard"), Some(Variant::Chess));
+        assert_eq!(variant_of("From Position"), Some(Variant::Chess));
+        assert_eq!(variant_of("Chess960"), Some(Variant::Chess));
+        assert_eq!(variant_of("Crazyhouse"), Some(Variant::Crazyhouse));
+        assert_eq!(variant_of("King of the Hill"), Some(Variant::KingOfTheHill));
+        assert_eq!(variant_of("Three-check"), Some(Variant::ThreeCheck));
+        assert_eq!(variant_of("Racing Kings"), Some(Variant::RacingKings));
+        assert_eq!(variant_of("Bughouse"), None);
+    }
+
+    #[test]
+    fn only_games_of_the_variant_of_the_visitor() {
+        // White has nothing in hand to block the check with.
+        let pgn = r#"[Event "Rated Crazyhouse game"]
+[Variant "Crazyhouse"]
+[WhiteElo "2000"]
+[BlackElo "2000"]
+[TimeControl "300+0"]
+[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+"#;
+        let mut visitor = MoveVisitor::new(None);
+        let skipped = BufferedReader::new_cursor(pgn).read_game(&mut visitor);
+        assert!(matches!(skipped, Ok(Some(None))));
+
+        let mut visitor = MoveVisitor::new(None).with_variant(Variant::Crazyhouse);
+        let samples = BufferedReader::new_cursor(pgn)
+            .read_game(&mut visitor)
+            .expect("valid PGN")
+            .flatten()
+            .expect("a decisive game");
+        assert_eq!(samples.len(), 4);
+        assert!(samples
+            .iter()
+            .all(|sample| Variant::from(sample.chess.variant()) == Variant::Crazyhouse));
+    }
+
+    #[test]
+    fn games_from_a_position_count_their_moves() {
+        // Fool's mate, from the position after 1. f3 e5.
+        let pgn = r#"[Event "Rated Blitz game"]
+[WhiteElo "2000"]
+[BlackElo "2000"]
+[TimeControl "300+0"]
+[Result "0-1"]
+[SetUp "1"]
+[FEN "rnbqkbnr/pppp1ppp/8/4p3/8/5P2/PPPPP1PP/RNBQKBNR w KQkq - 0 2"]
+
+2. g4 Qh4# 0-1
+"#;
+        let mut visitor = MoveVisitor::new(None);
+        let samples = BufferedReader::new_cursor(pgn)
+            .read_game(&mut visitor)
+            .expect("valid PGN")
+            .flatten()
+            .expect("a decisive game");
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].chess.fullmoves().get(), 2);
+        assert_eq!(
+            samples[1].label.to_uci(CastlingMode::Standard).to_string(),
+            "d8h4"
+        );
+        // Plies count from the start of the game, two before the FEN and two after it.
+        assert_eq!(visitor.move_count, 4);
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    error::Error,
+    fmt::{self, Display},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    iter,
+    path::PathBuf,
+    time::Instant,
+};
+
+use itertools::Itertools;
+use rand::Rng;
+use shakmaty::{Move, Position};
+
+use crate::{
+    backend::{self, OutputLabel},
+    castling_move_to_output, chess_to_input,
+    dataset::NPY_FILES_DIR,
+    features::FEATURE_PLANES,
+    flip_chess, flip_output,
+    history::{history_planes, History},
+    manifest::{
+        Castling, Compression, Encoding, Format, Layout, Manifest, Packing, Perspective, Variant,
+    },
+    mirror_chess, mirror_output,
+    nnue::NNUE_MAX_FEATURES,
+    policy,
+    variant::{position_to_variant_inputs, variant_inputs},
+    Sample, INPUT_LENGTH, PLANES, SPARSE_INPUT_LENGTH,
+};
+
+/// Everything that can go wrong while writing a dataset.
+#[derive(Debug)]
+pub enum DatasetError {
+    /// The configuration combines options that do not work together, or does not fit the labels.
+    InvalidConfig(String),
+    Io(io::Error),
+}
+
+impl Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatasetError::InvalidConfig(reason) => write!(f, "invalid dataset config: {reason}"),
+            DatasetError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for DatasetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DatasetError::InvalidConfig(_) => None,
+            DatasetError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for DatasetError {
+    fn from(err: io::Error) -> Self {
+        DatasetError::Io(err)
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> DatasetError {
+    DatasetError::InvalidConfig(reason.into())
+}
+
+/// How positions are turned into the stored inputs.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputConfig {
+    pub encoding: Encoding,
+    /// Columnar formats always store bit-packed inputs.
+    pub packing: Packing,
+    pub layout: Layout,
+    pub perspective: Perspective,
+    /// Append the planes of [`chess_to_features`](crate::features::chess_to_features).
+    pub tactical_features: bool,
+}
+
+/// Soft policy targets, see [`policy::aggregate`] and [`policy::softmax`].
+#[derive(Debug, Clone, Copy)]
+pub struct PolicyConfig {
+    pub top_k: usize,
+    pub elo_weighting: bool,
+    /// Take the targets of the samples, computed from engine lines with this softmax
+    /// temperature, instead of aggregating the played moves.
+    pub temperature: Option<f32>,
+}
+
+/// The options of a dataset, as given to [`DatasetWriterBuilder::config`].
+#[derive(Debug, Clone)]
+pub struct DatasetConfig {
+    /// The number of positions to write. Has to be a multiple of `boards_per_file`.
+    pub total: usize,
+    pub boards_per_file: usize,
+    pub format: Format,
+    pub compression: Compression,
+    pub input: InputConfig,
+    /// The probability with which a horizontally mirrored copy of a position is added.
+    pub mirror_augmentation: Option<f64>,
+    pub legal_moves: bool,
+    /// How castling moves are labelled by [`DatasetWriter::write_moves`].
+    pub castling: Castling,
+    /// The variant of all positions.
+    pub variant: Variant,
+    /// The number of earlier boards of the history planes, if they are written.
+    pub history: Option<usize>,
+    pub policy: Option<PolicyConfig>,
+    /// Print the progress and an ETA to stderr.
+    pub progress: bool,
+}
+
+impl Default for DatasetConfig {
+    fn default() -> Self {
+        Self {
+            total: 0,
+            boards_per_file: 500_000,
+            format: Format::Npy,
+            compression: Compression::None,
+            input: InputConfig::default(),
+            mirror_augmentation: None,
+            legal_moves: false,
+            castling: Castling::KingDestination,
+            variant: Variant::Chess,
+            history: None,
+            policy: None,
+            progress: false,
+        }
+    }
+}
+
+/// Builds a [`DatasetWriter`], checking that the options fit together.
+///
+/// ```ignore
+/// let writer = DatasetWriter::builder("2017-01")
+///     .total(1_000_000)
+///     .boards_per_file(100_000)
+///     .format(Format::Npz)
+///     .build()?;
+/// writer.write_moves(samples)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct DatasetWriterBuilder {
+    dir: PathBuf,
+    prefix: String,
+    config: DatasetConfig,
+}
+
+impl DatasetWriterBuilder {
+    /// The directory the dataset and its manifest are written to, [`NPY_FILES_DIR`] by default.
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Replaces all options.
+    pub fn config(mut self, config: DatasetConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn total(mut self, total: usize) -> Self {
+        self.config.total = total;
+        self
+    }
+
+    pub fn boards_per_file(mut self, boards_per_file: usize) -> Self {
+        self.config.boards_per_file = boards_per_file;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.config.format = format;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    pub fn input(mut self, input: InputConfig) -> Self {
+        self.config.input = input;
+        self
+    }
+
+    pub fn build(self) -> Result<DatasetWriter, DatasetError> {
+        let manifest = manifest(&self.config)?;
+        Ok(DatasetWriter {
+            dir: self.dir,
+            prefix: self.prefix,
+            config: self.config,
+            manifest,
+        })
+    }
+}
+
+/// Writes positions and their labels as a dataset in the output directory, together with its
+/// manifest.
+#[derive(Debug, Clone)]
+pub struct DatasetWriter {
+    dir: PathBuf,
+    prefix: String,
+    config: DatasetConfig,
+    manifest: Manifest,
+}
+
+impl DatasetWriter {
+    /// Starts building a writer for the dataset with the given prefix, e.g. `2017-01`.
+    pub fn builder(prefix: impl Into<String>) -> DatasetWriterBuilder {
+        DatasetWriterBuilder {
+            dir: PathBuf::from(NPY_FILES_DIR),
+            prefix: prefix.into(),
+            config: DatasetConfig::default(),
+        }
+    }
+
+    /// The manifest the dataset will be written with (with `files` still at zero).
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Writes samples with played moves, labelled by their [`castling_move_to_output`] index.
+    pub fn write_moves(
+        self,
+        samples: impl Iterator<Item = Sample<Move>>,
+    ) -> Result<Manifest, DatasetError> {
+        let castling = self.config.castling;
+        self.write(samples.map(|sample| Sample {
+            label: castling_move_to_output(&sample.label, castling),
+            chess: sample.chess,
+            game: sample.game,
+            policy: sample.policy,
+            history: sample.history,
+        }))
+    }
+
+    /// Deduplicates, augments and chunks the samples and writes the first `total` of them.
+    /// Returns the written manifest.
+    pub fn write<T: OutputLabel + 'static>(
+        self,
+        samples: impl Iterator<Item = Sample<T>>,
+    ) -> Result<Manifest, DatasetError> {
+        let DatasetWriter {
+            dir,
+            prefix,
+            config,
+            mut manifest,
+        } = self;
+
+        let samples: Box<dyn Iterator<Item = Sample<T>>> = match config.policy {
+            Some(policy) if policy.temperature.is_none() => Box::new(
+                policy::aggregate(samples, policy.top_k, policy.elo_weighting)?.into_iter(),
+            ),
+            _ => Box::new(samples),
+        };
+        // Reject samples that do not fit the dataset before any file is created, as far as the
+        // first one tells.
+        let mut samples = samples.peekable();
+        if let Some(sample) = samples.peek() {
+            check_sample(&manifest, sample)?;
+        }
+
+        let mut backend = backend::create::<T>(&dir, &prefix, &manifest)?;
+
+        let mut rng = rand::thread_rng();
+        let perspective = config.input.perspective;
+        let samples_chunked = samples
+            .flat_map(|sample| {
+                let flipped = config
+                    .mirror_augmentation
+                    .filter(|&probability| rng.gen_bool(probability))
+                    .and_then(|_| flip_chess(&sample.chess))
+                    .map(|chess| Sample {
+                        chess,
+                        label: sample.label.flipped(),
+                        game: sample.game.clone(),
+                        policy: sample
+                            .policy
+                            .as_ref()
+                            .map(|policy| policy::map_moves(policy, flip_output)),
+                        history: sample.history.as_ref().map(History::flipped),
+                    });
+                iter::once(sample).chain(flipped)
+            })
+            // The config rejects the variants that can not be mirrored.
+            .filter_map(|sample| match perspective {
+                Perspective::SideToMove if sample.chess.turn().is_black() => Some(Sample {
+                    chess: mirror_chess(&sample.chess)?,
+                    label: sample.label.mirrored(),
+                    policy: sample
+                        .policy
+                        .as_ref()
+                        .map(|policy| policy::map_moves(policy, mirror_output)),
+                    history: sample.history.as_ref().map(History::mirrored),
+                    ..sample
+                }),
+                _ => Some(sample),
+            })
+            .map(|sample| (chess_to_input(&sample.chess), sample))
+            .unique_by(|(input, sample)| {
+                let mut hasher = DefaultHasher::new();
+                input.hash(&mut hasher);
+                position_to_variant_inputs(&sample.chess).hash(&mut hasher);
+                hasher.finish()
+            })
+            .chunks(config.boards_per_file);
+
+        let start_time = Instant::now();
+
+        for (chunk_index, chunk) in samples_chunked
+            .into_iter()
+            .take(config.total / config.boards_per_file)
+            .enumerate()
+        {
+            backend.begin_chunk(chunk_index)?;
+
+            for (board_index, (input, sample)) in chunk.enumerate() {
+                if config.progress {
+                    // i is the index in the chunk, not the total index
+                    // so we need to add the chunk index to it
+                    debug(
+                        start_time,
+                        chunk_index * config.boards_per_file + board_index,
+                        config.total,
+                    );
+                }
+                check_sample(&manifest, &sample)?;
+                backend.push(&input, &sample)?;
+            }
+
+            backend.finish_chunk()?;
+            manifest.files += 1;
+        }
+
+        manifest.write(&dir, &prefix)?;
+
+        Ok(manifest)
+    }
+}
+
+/// Checks that a sample can be written to the dataset described by `manifest`.
+fn check_sample<T>(manifest: &Manifest, sample: &Sample<T>) -> Result<(), DatasetError> {
+    if manifest.policy_temperature.is_some() && sample.policy.is_none() {
+        return Err(invalid(
+            "soft policy targets from engine lines need samples with engine lines",
+        ));
+    }
+    let variant = Variant::from(sample.chess.variant());
+    if variant != manifest.variant {
+        return Err(invalid(format!(
+            "a {variant:?} position can not be written to a {:?} dataset",
+            manifest.variant
+        )));
+    }
+    Ok(())
+}
+
+/// Checks the options and describes the dataset they produce.
+fn manifest(config: &DatasetConfig) -> Result<Manifest, DatasetError> {
+    let DatasetConfig {
+        total,
+        boards_per_file,
+        format,
+        compression,
+        input,
+        mirror_augmentation,
+        legal_moves,
+        castling,
+        variant,
+        history,
+        policy,
+        progress: _,
+    } = *config;
+
+    if boards_per_file == 0 || !total.is_multiple_of(boards_per_file) {
+        return Err(invalid(format!(
+            "the total of {total} boards is not a multiple of {boards_per_file} boards per file"
+        )));
+    }
+    match (format, compression) {
+        (_, Compression::None)
+        | (Format::Npz, Compression::Deflate | Compression::Zstd)
+        | (Format::Tfrecord, Compression::Gzip) => {}
+        _ => {
+            return Err(invalid(format!(
+                "{compression:?} compression is not supported for {format:?} datasets"
+            )))
+        }
+    }
+    let columnar = matches!(format, Format::Tfrecord | Format::Parquet | Format::Arrow);
+    if input.layout != Layout::Flat && columnar {
+        return Err(invalid(format!(
+            "the plane layout is not supported for {format:?} datasets"
+        )));
+    }
+    if input.layout != Layout::Flat && input.packing != Packing::None {
+        return Err(invalid("the plane layout can not be packed or sparse"));
+    }
+    if mirror_augmentation.is_some_and(|probability| !(0.0..=1.0).contains(&probability)) {
+        return Err(invalid(
+            "the mirror augmentation probability must be between 0 and 1",
+        ));
+    }
+    if legal_moves && columnar {
+        return Err(invalid(format!(
+            "legal move masks are not supported for {format:?} datasets"
+        )));
+    }
+    if policy.is_some_and(|policy| policy.top_k == 0) {
+        return Err(invalid("soft policy targets need at least one move"));
+    }
+    if let Some(temperature) = policy.and_then(|policy| policy.temperature) {
+        if temperature <= 0.0 || temperature.is_nan() {
+            return Err(invalid("the softmax temperature must be positive"));
+        }
+        if policy.is_some_and(|policy| policy.elo_weighting) {
+            return Err(invalid(
+                "soft policy targets from engine lines can not be weighted by Elo",
+            ));
+        }
+    }
+    if policy.is_some() && columnar {
+        return Err(invalid(format!(
+            "soft policy targets are not supported for {format:?} datasets"
+        )));
+    }
+    if input.encoding != Encoding::Board && columnar {
+        return Err(invalid(format!(
+            "NNUE encodings are not supported for {format:?} datasets"
+        )));
+    }
+    if input.encoding != Encoding::Board
+        && (input.layout != Layout::Flat
+            || input.packing != Packing::None
+            || input.tactical_features)
+    {
+        return Err(invalid(
+            "NNUE encodings can not be combined with planes, packing or tactical features",
+        ));
+    }
+    let features = input.tactical_features;
+    if features && columnar {
+        return Err(invalid(format!(
+            "tactical features are not supported for {format:?} datasets"
+        )));
+    }
+    if features && input.packing == Packing::Sparse {
+        return Err(invalid("tactical features can not be stored sparse"));
+    }
+    if history.is_some() && columnar {
+        return Err(invalid(format!(
+            "history planes are not supported for {format:?} datasets"
+        )));
+    }
+    let inputs = variant_inputs(variant);
+    if !inputs.is_empty() && columnar {
+        return Err(invalid(format!(
+            "{variant:?} inputs are not supported for {format:?} datasets"
+        )));
+    }
+    if matches!(variant, Variant::RacingKings | Variant::Horde)
+        && input.perspective == Perspective::SideToMove
+    {
+        return Err(invalid(format!(
+            "{variant:?} positions can not be mirrored to the side to move"
+        )));
+    }
+    // Horde starts with 36 white pawns, more than the NNUE feature arrays hold.
+    if variant == Variant::Horde && input.encoding != Encoding::Board {
+        return Err(invalid(format!(
+            "Horde positions can have more than {NNUE_MAX_FEATURES} pieces for the {:?} encoding",
+            input.encoding
+        )));
+    }
+
+    let layout = input.layout;
+    Ok(Manifest {
+        format,
+        encoding: input.encoding,
+        history: history.map(history_planes),
+        policy_top_k: policy.map(|policy| policy.top_k),
+        policy_elo_weighting: policy.is_some_and(|policy| policy.elo_weighting),
+        policy_temperature: policy.and_then(|policy| policy.temperature),
+        legal_moves,
+        castling,
+        variant,
+        variant_inputs: (!inputs.is_empty())
+            .then(|| inputs.iter().map(|&input| input.to_owned()).collect()),
+        perspective: input.perspective,
+        mirror_augmentation,
+        compression,
+        packing: if columnar {
+            Packing::Packbits
+        } else {
+            input.packing
+        },
+        sparse_width: (input.packing == Packing::Sparse && !columnar)
+            .then_some(SPARSE_INPUT_LENGTH),
+        input_length: INPUT_LENGTH
+            + if features {
+                FEATURE_PLANES.len() * 64
+            } else {
+                0
+            },
+        features: features.then(|| FEATURE_PLANES.map(String::from).to_vec()),
+        layout,
+        planes: (layout != Layout::Flat).then(|| {
+            let features = if features { &FEATURE_PLANES[..] } else { &[] };
+            PLANES
+                .iter()
+                .chain(features)
+                .map(|&plane| plane.to_owned())
+                .collect()
+        }),
+        boards_per_file,
+        ..Manifest::default()
+    })
+}
+
+fn debug(start_time: Instant, count: usize, total_data: usize) {
+    if count.is_multiple_of(1024) && count != 0 {
+        let amount_of_boards = total_data;
+        let remaining = amount_of_boards - count;
+        let elapsed = start_time.elapsed();
+        let time_per_board = elapsed / count as u32;
+
+        let raw_eta = remaining as u32 * time_per_board;
+        // Format the eta as a HH:MM:SS string
+        let eta = format!(
+            "{}:{:02}:{:02}",
+            raw_eta.as_secs() / 3600,
+            (raw_eta.as_secs() % 3600) / 60,
+            raw_eta.as_secs() % 60
+        );
+        eprint!(
+            "{count} / {board_amount} ({:.3}%) - {board_time:.4}ms per board - ETA: {eta}\r",
+            count as f32 * 100. / amount_of_boards as f32,
+            board_time = time_per_board.as_secs_f32() * 1000.0,
+            board_amount = total_data,
+        );
+        io::stderr().flush().expect("Couldn't flush stdout");
+    }
+}
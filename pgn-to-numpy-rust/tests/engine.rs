@@ -0,0 +1,139 @@
+//! Runs the engine pool against `examples/stub_engine.rs`, which `cargo test` builds with the
+//! other targets.
+
+#![cfg(feature = "datasets")]
+
+use std::{env, fs, path::PathBuf};
+
+use neural_chess::{
+    castling_move_to_output,
+    engine::{EnginePool, Limit},
+    eval_to_output,
+    manifest::{Castling, Variant},
+    visitor::MoveVisitor,
+    DatasetConfig, DatasetWriter, PolicyConfig, Sample,
+};
+use npyz::NpyFile;
+use pgn_reader::BufferedReader;
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Move, Position};
+
+const FOOLS_MATE: &str = r#"[Event "Rated Blitz game"]
+[WhiteElo "2000"]
+[BlackElo "2000"]
+[TimeControl "300+0"]
+[Result "0-1"]
+
+1. f3 e5 2. g4 Qh4# 0-1
+"#;
+
+const MATED: &str = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+
+fn stub_engine() -> PathBuf {
+    let path = env::current_exe()
+        .expect("test executable")
+        .parent()
+        .and_then(|deps| deps.parent())
+        .expect("target directory")
+        .join("examples")
+        .join(format!("stub_engine{}", env::consts::EXE_SUFFIX));
+    assert!(
+        path.exists(),
+        "build the stub with `cargo build --example stub_engine`"
+    );
+    path
+}
+
+/// The positions of the game, then the final position in which White is mated.
+fn positions() -> Vec<Sample<()>> {
+    let mut reader = BufferedReader::new_cursor(FOOLS_MATE);
+    let game = reader
+        .read_game(&mut MoveVisitor::new(None))
+        .expect("valid PGN")
+        .flatten()
+        .expect("a decisive game");
+    let mated: Fen = MATED.parse().expect("valid FEN");
+    let mated: Chess = mated.into_position(CastlingMode::Standard).expect("legal");
+    game.into_iter()
+        .map(|sample: Sample<Move>| Sample::new(sample.chess, ()))
+        .chain([Sample::new(mated, ())])
+        .collect()
+}
+
+#[test]
+fn annotate_with_stub_engine() {
+    let pool = EnginePool::spawn(&stub_engine(), 2, 3, Variant::Chess).expect("stub starts");
+    let mut analysed = pool
+        .analyse(positions().into_iter(), Limit::Depth(1))
+        .collect::<Result<Vec<_>, _>>()
+        .expect("no engine errors");
+    assert_eq!(analysed.len(), 5);
+    // The searches finish in any order.
+    analysed.sort_by_key(|(sample, _)| (sample.chess.fullmoves(), sample.chess.turn().is_black()));
+
+    let (mated, analysis) = analysed.pop().expect("five positions");
+    assert!(mated.chess.is_checkmate());
+    assert!(analysis.best_move.is_none());
+    assert!(analysis.lines.is_empty());
+    assert_eq!(analysis.eval(mated.chess.turn()), None);
+
+    let (before_mate, analysis) = &analysed[3];
+    let queen_mate: Uci = "d8h4".parse().expect("valid UCI");
+    assert_eq!(analysis.best_move.as_ref(), Some(&queen_mate));
+    assert_eq!(analysis.lines.len(), 3);
+    assert_eq!(
+        analysis.eval(before_mate.chess.turn()),
+        Some(f32::NEG_INFINITY)
+    );
+    let queen_mate = castling_move_to_output(
+        &queen_mate.to_move(&before_mate.chess).expect("legal"),
+        Castling::KingDestination,
+    );
+
+    let dir = env::temp_dir().join(format!("neural-chess-engine-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("temporary directory");
+    let samples = analysed.into_iter().map(|(sample, analysis)| Sample {
+        label: eval_to_output(analysis.eval(sample.chess.turn()).expect("a line")),
+        policy: analysis.policy(&sample.chess, Castling::KingDestination, 1.0),
+        chess: sample.chess,
+        game: None,
+        history: None,
+    });
+    DatasetWriter::builder("annotated")
+        .output_dir(&dir)
+        .config(DatasetConfig {
+            total: 4,
+            boards_per_file: 4,
+            policy: Some(PolicyConfig {
+                top_k: 3,
+                elo_weighting: false,
+                temperature: Some(1.0),
+            }),
+            ..DatasetConfig::default()
+        })
+        .build()
+        .expect("valid config")
+        .write(samples)
+        .expect("dataset written");
+
+    let read = |array: &str| fs::File::open(dir.join(format!("annotated_{array}/0.npy")));
+    let labels: Vec<f32> = NpyFile::new(read("output").expect("labels"))
+        .and_then(NpyFile::into_vec)
+        .expect("f32 labels");
+    let moves: Vec<u16> = NpyFile::new(read("policy_moves").expect("policy moves"))
+        .and_then(NpyFile::into_vec)
+        .expect("u16 moves");
+    let probabilities: Vec<f32> = NpyFile::new(read("policy_probs").expect("probabilities"))
+        .and_then(NpyFile::into_vec)
+        .expect("f32 probabilities");
+    fs::remove_dir_all(&dir).expect("temporary directory");
+
+    // The stub only sees material, so the opening moves are all equal.
+    assert_eq!(labels, [0.5, 0.5, 0.5, 0.0]);
+    for row in probabilities[..9].chunks(3) {
+        for &probability in row {
+            assert!((probability - 1.0 / 3.0).abs() < 1e-6);
+        }
+    }
+    assert_eq!(moves[9], queen_mate);
+    assert!((probabilities[9] - 1.0).abs() < 1e-6);
+}
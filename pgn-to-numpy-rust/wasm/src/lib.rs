@@ -0,0 +1,61 @@
+//! WebAssembly bindings for the encoders of `neural_chess`, so the frontend feeds its models
+//! exactly the inputs they were trained on. Built with wasm-pack:
+//!
+//! ```text
+//! cd frontend && npm run build-wasm
+//! ```
+
+use neural_chess::{
+    chess_to_input, legal_move_mask, manifest::Castling, move_to_output, output_to_move,
+};
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Position};
+use wasm_bindgen::prelude::*;
+
+fn parse_fen(fen: &str) -> Result<Chess, JsError> {
+    let parsed = fen
+        .parse::<Fen>()
+        .map_err(|err| JsError::new(&format!("invalid FEN {fen:?}: {err}")))?;
+    let mode = CastlingMode::detect(parsed.as_setup());
+    parsed
+        .into_position(mode)
+        .map_err(|err| JsError::new(&format!("illegal position {fen:?}: {err}")))
+}
+
+/// Encodes a position like `chess_to_input`, as a `Float32Array` of length 833.
+#[wasm_bindgen(js_name = fenToInput)]
+pub fn fen_to_input(fen: &str) -> Result<Vec<f32>, JsError> {
+    Ok(chess_to_input(&parse_fen(fen)?)
+        .map(|value| if value { 1.0 } else { 0.0 })
+        .to_vec())
+}
+
+/// The index (`from * 64 + to`) of a legal move, given in UCI notation.
+#[wasm_bindgen(js_name = moveToIndex)]
+pub fn move_to_index(fen: &str, uci: &str) -> Result<u16, JsError> {
+    let chess = parse_fen(fen)?;
+    let m = uci
+        .parse::<Uci>()
+        .map_err(|err| JsError::new(&format!("invalid UCI move {uci:?}: {err}")))?
+        .to_move(&chess)
+        .map_err(|err| JsError::new(&format!("illegal move {uci:?}: {err}")))?;
+    Ok(move_to_output(&m))
+}
+
+/// The legal move with the given index in UCI notation, preferring queen promotions.
+#[wasm_bindgen(js_name = indexToMove)]
+pub fn index_to_move(fen: &str, index: u16) -> Result<Option<String>, JsError> {
+    let chess = parse_fen(fen)?;
+    Ok(output_to_move(&chess, index)
+        .map(|m| Uci::from_move(&m, chess.castles().mode()).to_string()))
+}
+
+/// One byte per move index, `1` if the move is legal, e.g. to mask the policy output.
+///
+/// Unlike the `legal` arrays of a dataset, the mask is not packed into bits.
+#[wasm_bindgen(js_name = legalMoveMask)]
+pub fn legal_moves(fen: &str) -> Result<Vec<u8>, JsError> {
+    let packed = legal_move_mask(&parse_fen(fen)?.into(), Castling::KingDestination);
+    Ok((0..packed.len() * 8)
+        .map(|index| packed[index / 8] >> (7 - index % 8) & 1)
+        .collect())
+}
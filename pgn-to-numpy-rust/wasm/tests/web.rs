@@ -0,0 +1,84 @@
+//! Smoke tests of the bindings against the encoders of `neural_chess`. They run natively with
+//! `cargo test` and in a JavaScript engine with
+//! `wasm-pack test --node pgn-to-numpy-rust/wasm`.
+
+use std::collections::BTreeSet;
+
+use neural_chess::{
+    chess_to_input, legal_move_mask, manifest::Castling, move_to_output, output_to_move,
+};
+use neural_chess_wasm::{fen_to_input, index_to_move, legal_moves, move_to_index};
+use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Position};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+const FENS: [&str; 3] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+    "1r2k3/2P5/8/8/8/8/8/4K3 w - - 0 1",
+];
+
+fn position(fen: &str) -> Chess {
+    let fen: Fen = fen.parse().expect("valid FEN");
+    fen.into_position(CastlingMode::Standard)
+        .expect("legal position")
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn fen_to_input_matches_chess_to_input() {
+    for fen in FENS {
+        let expected = chess_to_input(&position(fen)).map(f32::from);
+        assert_eq!(fen_to_input(fen).expect("valid FEN"), expected);
+    }
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn move_indices_match_move_to_output() {
+    for fen in FENS {
+        let chess = position(fen);
+        for m in chess.legal_moves() {
+            let uci = Uci::from_move(&m, CastlingMode::Standard).to_string();
+            let index = move_to_index(fen, &uci).expect("legal move");
+            assert_eq!(index, move_to_output(&m));
+            let expected = output_to_move(&chess, index)
+                .map(|m| Uci::from_move(&m, CastlingMode::Standard).to_string());
+            assert_eq!(index_to_move(fen, index).expect("valid FEN"), expected);
+        }
+    }
+    // e2e4 and castling by the king's destination.
+    assert_eq!(move_to_index(FENS[0], "e2e4").ok(), Some(12 * 64 + 28));
+    assert_eq!(
+        index_to_move(FENS[1], 60 * 64 + 62)
+            .ok()
+            .flatten()
+            .as_deref(),
+        Some("e8g8")
+    );
+    // Promotions prefer the queen.
+    assert_eq!(
+        index_to_move(FENS[2], 50 * 64 + 57)
+            .ok()
+            .flatten()
+            .as_deref(),
+        Some("c7b8q")
+    );
+    assert_eq!(index_to_move(FENS[0], 0).ok().flatten(), None);
+}
+
+#[wasm_bindgen_test(unsupported = test)]
+fn legal_move_mask_unpacks_the_packed_mask() {
+    for fen in FENS {
+        let chess = position(fen);
+        let packed = legal_move_mask(&chess.clone().into(), Castling::KingDestination);
+        let mask = legal_moves(fen).expect("valid FEN");
+        assert_eq!(mask.len(), 64 * 64);
+        for (index, &legal) in mask.iter().enumerate() {
+            assert_eq!(legal, packed[index / 8] >> (7 - index % 8) & 1);
+        }
+        // Promotions to different pieces share an index.
+        let indices: BTreeSet<u16> = chess.legal_moves().iter().map(move_to_output).collect();
+        assert_eq!(
+            mask.iter().map(|&legal| usize::from(legal)).sum::<usize>(),
+            indices.len()
+        );
+    }
+}